@@ -1,3 +1,5 @@
 fn main() {
+    // ヘッドレスサーバーのみをビルドする場合（--no-default-features）はTauriの設定を読み込まない
+    #[cfg(feature = "desktop")]
     tauri_build::build()
 }
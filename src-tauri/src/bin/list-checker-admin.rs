@@ -0,0 +1,320 @@
+// 起動中のサーバーを管理APIで操作する（管理APIのトークンを設定したサーバーのみ）
+//
+// list-checker-admin [--server http://<ホスト>:50345] [--token <トークン>] <コマンド> ...
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const SERVER_ENV: &str = "LIST_CHECKER_SERVER";
+const TOKEN_ENV: &str = "LIST_CHECKER_ADMIN_TOKEN";
+const DEFAULT_SERVER: &str = "http://127.0.0.1:50345";
+
+const USAGE: &str = "使い方:
+  list-checker-admin [--server <URL>] [--token <トークン>] <コマンド>
+
+コマンド:
+  events                                 イベントの一覧
+  status   <イベントID>                  出席数・接続端末数・設定
+  register <イベントID> <参加者ID>       出席を登録
+  remove   <イベントID> <参加者ID>       出席を取り消す
+  settings <イベントID> [--arrowtoday <true|false>] [--autotodayregister <true|false>]
+           [--soukai <true|false>] [--nolist <true|false>] [--requirecheckout <true|false>]
+  export   <イベントID> [--session <セッションID>] [--output <ファイル>]
+  join-code <イベントID>                 参加コードを再発行（以前のコードは使えなくなる）
+  tokens   <イベントID>                  端末トークンの一覧
+  issue-token  <イベントID> <端末名> [--role <kiosk|monitor>] [--hours <有効時間>]
+  revoke-token <イベントID> <トークンID>   端末トークンを無効にする（接続中の端末は切断）
+  require-token <イベントID> <true|false>  端末トークンを持たない端末の参加を禁止する
+  self-checkin  <イベントID> <true|false>  学生のスマートフォンからのセルフ出席登録を受け付ける
+  qr-checkin    <イベントID> <true|false>  モニタのQRコードを読み取った端末からのみ出席登録を受け付ける
+  import-cards  <イベントID> <ファイル> [--replace <true|false>]
+                                         カード番号と参加者IDの対応表（CSV）を読み込む
+
+--server と --token を省略した場合は、環境変数 LIST_CHECKER_SERVER と LIST_CHECKER_ADMIN_TOKEN を使います。";
+
+const SETTING_NAMES: [&str; 5] = ["arrowtoday", "autotodayregister", "soukai", "nolist", "requirecheckout"];
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = std::env::args().skip(1);
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = args.next().ok_or_else(|| format!("--{} の値を指定してください", name))?;
+                    options.insert(name.to_string(), value);
+                }
+                None => positional.push(arg),
+            }
+        }
+        if positional.is_empty() {
+            return Err(USAGE.to_string());
+        }
+        Ok(Self { positional, options })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn arg(&self, index: usize, name: &str) -> Result<&str, String> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| format!("{}を指定してください\n\n{}", name, USAGE))
+    }
+}
+
+struct Client {
+    http: reqwest::Client,
+    server: String,
+    token: String,
+}
+
+impl Client {
+    fn new(args: &Args) -> Result<Self, String> {
+        let server = args
+            .get("server")
+            .map(str::to_string)
+            .or_else(|| std::env::var(SERVER_ENV).ok())
+            .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+        let token = args
+            .get("token")
+            .map(str::to_string)
+            .or_else(|| std::env::var(TOKEN_ENV).ok())
+            .ok_or("管理APIのトークンを --token または LIST_CHECKER_ADMIN_TOKEN で指定してください")?;
+        Ok(Self {
+            http: reqwest::Client::new(),
+            server: server.trim_end_matches('/').to_string(),
+            token,
+        })
+    }
+
+    // パスの各要素はURLエンコードして連結する（参加者IDに記号が含まれる場合があるため）
+    fn url(&self, segments: &[&str]) -> Result<reqwest::Url, String> {
+        let mut url = reqwest::Url::parse(&self.server).map_err(|e| format!("サーバーのURLが正しくありません: {}", e))?;
+        url.path_segments_mut()
+            .map_err(|_| format!("サーバーのURLが正しくありません: {}", self.server))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    async fn send(&self, method: Method, url: reqwest::Url, body: Option<Value>) -> Result<reqwest::Response, String> {
+        let mut request = self.http.request(method, url).bearer_auth(&self.token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        request.send().await.map_err(|e| format!("サーバーに接続できませんでした: {}", e))
+    }
+
+    // 応答 { ok, error_code, message, data } の data を返す
+    async fn call(&self, method: Method, segments: &[&str], body: Option<Value>) -> Result<Value, String> {
+        let response = self.send(method, self.url(segments)?, body).await?;
+        let status = response.status();
+        let response: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("サーバーの応答を読み込めませんでした（{}）: {}", status, e))?;
+        if response["ok"].as_bool() != Some(true) {
+            return Err(format!(
+                "{}（{}）",
+                response["message"].as_str().unwrap_or("エラーが発生しました"),
+                response["error_code"].as_str().unwrap_or_else(|| status.as_str())
+            ));
+        }
+        Ok(response["data"].clone())
+    }
+}
+
+async fn events(client: &Client) -> Result<(), String> {
+    let events = client.call(Method::GET, &["api", "events"], None).await?;
+    for event in events.as_array().into_iter().flatten() {
+        println!(
+            "{}\t{}\t出席 {}/{}\t当日 {}\t端末 {}",
+            event["id"].as_str().unwrap_or_default(),
+            event["name"].as_str().unwrap_or_default(),
+            event["attendees"],
+            event["participants"],
+            event["ontheday"],
+            event["devices_online"]
+        );
+    }
+    Ok(())
+}
+
+async fn status(client: &Client, uuid: &str) -> Result<(), String> {
+    let event = client.call(Method::GET, &["api", "events", uuid], None).await?;
+    println!("{}", serde_json::to_string_pretty(&event).unwrap_or_default());
+    Ok(())
+}
+
+async fn register(client: &Client, uuid: &str, id: &str) -> Result<(), String> {
+    let result = client
+        .call(Method::POST, &["api", "events", uuid, "attendance"], Some(json!({ "id": id })))
+        .await?;
+    println!("{}: {}", id, result["status"].as_str().unwrap_or_default());
+    Ok(())
+}
+
+async fn remove(client: &Client, uuid: &str, id: &str) -> Result<(), String> {
+    client.call(Method::DELETE, &["api", "events", uuid, "attendance", id], None).await?;
+    println!("{}: removed", id);
+    Ok(())
+}
+
+// 指定した項目だけを変更し、残りは現在の設定を引き継ぐ
+async fn settings(client: &Client, args: &Args, uuid: &str) -> Result<(), String> {
+    let event = client.call(Method::GET, &["api", "events", uuid], None).await?;
+    let mut settings = event["settings"].clone();
+    for name in SETTING_NAMES {
+        if let Some(value) = args.get(name) {
+            let value: bool = value.parse().map_err(|_| format!("--{} には true または false を指定してください", name))?;
+            settings[name] = json!(value);
+        }
+    }
+    let updated = client
+        .call(Method::PUT, &["api", "events", uuid, "settings"], Some(settings))
+        .await?;
+    println!("{}", serde_json::to_string_pretty(&updated).unwrap_or_default());
+    Ok(())
+}
+
+async fn join_code(client: &Client, uuid: &str) -> Result<(), String> {
+    let code = client.call(Method::POST, &["api", "events", uuid, "join_code"], None).await?;
+    println!("{}", code.as_str().unwrap_or_default());
+    Ok(())
+}
+
+async fn tokens(client: &Client, uuid: &str) -> Result<(), String> {
+    let tokens = client.call(Method::GET, &["api", "events", uuid, "device_tokens"], None).await?;
+    for token in tokens.as_array().into_iter().flatten() {
+        println!(
+            "{}\t{}\t{}\t期限 {}",
+            token["id"].as_str().unwrap_or_default(),
+            token["device_name"].as_str().unwrap_or_default(),
+            token["role"].as_str().unwrap_or_default(),
+            token["expires_at"].as_str().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+async fn issue_token(client: &Client, args: &Args, uuid: &str, device_name: &str) -> Result<(), String> {
+    let mut body = json!({ "device_name": device_name });
+    if let Some(role) = args.get("role") {
+        body["role"] = json!(role);
+    }
+    if let Some(hours) = args.get("hours") {
+        let hours: i64 = hours.parse().map_err(|_| format!("--hours には時間数を指定してください: {}", hours))?;
+        body["valid_hours"] = json!(hours);
+    }
+    let token = client
+        .call(Method::POST, &["api", "events", uuid, "device_tokens"], Some(body))
+        .await?;
+    println!("ID: {}", token["id"].as_str().unwrap_or_default());
+    println!("トークン: {}", token["token"].as_str().unwrap_or_default());
+    println!("有効期限: {}", token["expires_at"].as_str().unwrap_or_default());
+    Ok(())
+}
+
+async fn revoke_token(client: &Client, uuid: &str, id: &str) -> Result<(), String> {
+    client.call(Method::DELETE, &["api", "events", uuid, "device_tokens", id], None).await?;
+    println!("{}: revoked", id);
+    Ok(())
+}
+
+async fn require_token(client: &Client, uuid: &str, required: &str) -> Result<(), String> {
+    let required: bool = required.parse().map_err(|_| "true または false を指定してください".to_string())?;
+    client
+        .call(Method::PUT, &["api", "events", uuid, "require_device_token"], Some(json!({ "required": required })))
+        .await?;
+    println!("require_device_token: {}", required);
+    Ok(())
+}
+
+// self_checkin・qr_checkin の有効・無効を切り替える
+async fn enable(client: &Client, uuid: &str, feature: &str, enabled: &str) -> Result<(), String> {
+    let enabled: bool = enabled.parse().map_err(|_| "true または false を指定してください".to_string())?;
+    client
+        .call(Method::PUT, &["api", "events", uuid, feature], Some(json!({ "enabled": enabled })))
+        .await?;
+    println!("{}: {}", feature, enabled);
+    Ok(())
+}
+
+async fn import_cards(client: &Client, args: &Args, uuid: &str, path: &str) -> Result<(), String> {
+    let csv = std::fs::read_to_string(path).map_err(|e| format!("ファイルを読み込めませんでした: {}", e))?;
+    let replace: bool = match args.get("replace") {
+        Some(replace) => replace.parse().map_err(|_| "--replace には true または false を指定してください".to_string())?,
+        None => false,
+    };
+    let result = client
+        .call(Method::POST, &["api", "events", uuid, "card_ids"], Some(json!({ "csv": csv, "replace": replace })))
+        .await?;
+    println!(
+        "{}件を読み込みました（{}件は名簿にないため読み込みませんでした）",
+        result["imported"].as_u64().unwrap_or_default(),
+        result["skipped"].as_u64().unwrap_or_default()
+    );
+    Ok(())
+}
+
+async fn export(client: &Client, args: &Args, uuid: &str) -> Result<(), String> {
+    let mut url = client.url(&["api", "events", uuid, "export"])?;
+    if let Some(session) = args.get("session") {
+        url.query_pairs_mut().append_pair("session", session);
+    }
+    let response = client.send(Method::GET, url, None).await?;
+    if response.status() != StatusCode::OK {
+        let error: Value = response.json().await.unwrap_or_default();
+        return Err(error["message"].as_str().unwrap_or("書き出しに失敗しました").to_string());
+    }
+    let csv = response.text().await.map_err(|e| format!("サーバーの応答を読み込めませんでした: {}", e))?;
+    match args.get("output") {
+        Some(path) => std::fs::write(path, csv).map_err(|e| format!("ファイルに書き出せませんでした: {}", e)),
+        None => {
+            print!("{}", csv);
+            Ok(())
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let client = Client::new(&args)?;
+    match args.arg(0, "コマンド")? {
+        "events" => events(&client).await,
+        "status" => status(&client, args.arg(1, "イベントID")?).await,
+        "register" => register(&client, args.arg(1, "イベントID")?, args.arg(2, "参加者ID")?).await,
+        "remove" => remove(&client, args.arg(1, "イベントID")?, args.arg(2, "参加者ID")?).await,
+        "settings" => settings(&client, &args, args.arg(1, "イベントID")?).await,
+        "export" => export(&client, &args, args.arg(1, "イベントID")?).await,
+        "join-code" => join_code(&client, args.arg(1, "イベントID")?).await,
+        "tokens" => tokens(&client, args.arg(1, "イベントID")?).await,
+        "issue-token" => issue_token(&client, &args, args.arg(1, "イベントID")?, args.arg(2, "端末名")?).await,
+        "revoke-token" => revoke_token(&client, args.arg(1, "イベントID")?, args.arg(2, "トークンID")?).await,
+        "require-token" => require_token(&client, args.arg(1, "イベントID")?, args.arg(2, "true または false")?).await,
+        "self-checkin" => enable(&client, args.arg(1, "イベントID")?, "self_checkin", args.arg(2, "true または false")?).await,
+        "import-cards" => import_cards(&client, &args, args.arg(1, "イベントID")?, args.arg(2, "ファイル")?).await,
+        "qr-checkin" => enable(&client, args.arg(1, "イベントID")?, "qr_checkin", args.arg(2, "true または false")?).await,
+        command => Err(format!("不明なコマンドです: {}\n\n{}", command, USAGE)),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let result = match Args::parse() {
+        Ok(args) => run(args).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,311 @@
+// デスクトップアプリを使わずにSocket.IO + HTTPサーバーを起動する（Raspberry Piや学内サーバー向け）
+//
+// list-checker-server serve  --state events.json [--port 50345] [--admin-token <トークン>]
+// list-checker-server create --state events.json --name <イベント名> [--info <説明>] [--participants <ファイル>] [--allow-ontheday] [--join-code] [--self-checkin] [--qr-checkin]
+// list-checker-server import --state events.json --event <イベントID> --participants <ファイル>
+// list-checker-server import-cards --state events.json --event <イベントID> --cards <ファイル> [--replace]
+// list-checker-server export --state events.json --event <イベントID> [--session <セッションID>] [--output <ファイル>]
+// list-checker-server list   --state events.json
+use list_checker_tauri_lib::{
+    create_event, get_event_store, init_stderr_logging, set_admin_token, start_socketio_server, Eventstruct,
+    EVENT_NOT_FOUND, SOCKET_PORT,
+};
+use log::{error, info};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const DEFAULT_STATE_FILE: &str = "events.json";
+
+// 起動中に状態を保存する間隔
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+const USAGE: &str = "使い方:
+  list-checker-server serve  [--state <ファイル>] [--port <ポート>] [--admin-token <トークン>]
+  list-checker-server create [--state <ファイル>] --name <イベント名> [--info <説明>] [--participants <ファイル>] [--allow-ontheday] [--join-code] [--self-checkin] [--qr-checkin]
+  list-checker-server import [--state <ファイル>] --event <イベントID> --participants <ファイル>
+  list-checker-server import-cards [--state <ファイル>] --event <イベントID> --cards <ファイル> [--replace]
+  list-checker-server export [--state <ファイル>] --event <イベントID> [--session <セッションID>] [--output <ファイル>]
+  list-checker-server list   [--state <ファイル>]
+
+--state を省略した場合は events.json を使います。
+create・import・import-cards は同じファイルで serve を起動している間は実行できません（list-checker-admin から操作してください）。
+--admin-token（または環境変数 LIST_CHECKER_ADMIN_TOKEN）を指定すると、list-checker-admin から操作できる管理APIが有効になります。
+--join-code を指定すると、イベントIDの代わりに入力できる6文字の参加コードも発行します。
+--self-checkin を指定すると、学生が自分のスマートフォンから出席登録できます（self-checkin.html）。
+--qr-checkin を指定すると、モニタに表示したQRコードを読み取った端末からのみ出席登録できます（主催者が用意した端末を除く）。
+参加者ファイルは1行に1人（CSVの場合は1列目）のIDを記載してください。
+カード番号のファイルは1列目に学生証のバーコード・ICカードの番号、2列目に参加者IDを記載したCSVです（--replace を指定すると以前の対応表を破棄します）。";
+
+struct Args {
+    command: String,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = std::env::args().skip(1);
+        let command = args.next().ok_or_else(|| USAGE.to_string())?;
+        let mut options = HashMap::new();
+        let mut flags = Vec::new();
+
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                return Err(format!("不明な引数です: {}\n\n{}", arg, USAGE));
+            };
+            match name {
+                "allow-ontheday" | "join-code" | "self-checkin" | "qr-checkin" | "replace" => flags.push(name.to_string()),
+                "state" | "port" | "admin-token" | "name" | "info" | "participants" | "cards" | "event" | "session"
+                | "output" => {
+                    let value = args.next().ok_or_else(|| format!("--{} の値を指定してください", name))?;
+                    options.insert(name.to_string(), value);
+                }
+                _ => return Err(format!("不明なオプションです: --{}\n\n{}", name, USAGE)),
+            }
+        }
+        Ok(Self { command, options, flags })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn require(&self, name: &str) -> Result<&str, String> {
+        self.get(name).ok_or_else(|| format!("--{} を指定してください", name))
+    }
+
+    fn state_file(&self) -> PathBuf {
+        PathBuf::from(self.get("state").unwrap_or(DEFAULT_STATE_FILE))
+    }
+}
+
+// 起動中のサーバーの自動保存で上書きされないよう、状態ファイルを書き換えるコマンドは同時に実行しない
+// （ロックファイルはプロセスの終了時に解放されるため、異常終了しても残らない）
+fn lock_state(path: &Path) -> Result<File, String> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = File::create(&lock_path).map_err(|e| format!("ロックファイルを作成できませんでした: {}", e))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(format!(
+            "{} は起動中のサーバーが使用しています。サーバーを停止するか、list-checker-admin から操作してください",
+            path.display()
+        )),
+        Err(TryLockError::Error(e)) => Err(format!("ロックファイルをロックできませんでした: {}", e)),
+    }
+}
+
+// 保存ファイルがあれば読み込む
+fn load_state(path: &Path) -> Result<(), String> {
+    if !path.exists() {
+        info!("State file {} does not exist, starting with no events", path.display());
+        return Ok(());
+    }
+    let loaded = get_event_store().load_from_file(path)?;
+    info!("Loaded {} events from {}", loaded, path.display());
+    Ok(())
+}
+
+fn save_state(path: &Path) -> Result<(), String> {
+    let saved = get_event_store().save_to_file(path)?;
+    info!("Saved {} events to {}", saved, path.display());
+    Ok(())
+}
+
+// 参加者ファイルを読み込む（空行と # で始まる行は無視する）
+fn read_participants(path: &str) -> Result<Vec<String>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("参加者ファイルを読み込めませんでした: {}", e))?;
+    Ok(content
+        .lines()
+        .map(|line| line.split(',').next().unwrap_or_default().trim().trim_matches('"').to_string())
+        .filter(|id| !id.is_empty() && !id.starts_with('#'))
+        .collect())
+}
+
+async fn serve(args: &Args) -> Result<(), String> {
+    let state_file = args.state_file();
+    let port = match args.get("port") {
+        Some(port) => port.parse().map_err(|_| format!("ポート番号が正しくありません: {}", port))?,
+        None => SOCKET_PORT,
+    };
+    if let Some(token) = args.get("admin-token") {
+        set_admin_token(token.to_string());
+    }
+    let _lock = lock_state(&state_file)?;
+    load_state(&state_file)?;
+
+    // 一定間隔で状態を保存する
+    let autosave_file = state_file.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(AUTOSAVE_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = get_event_store().save_to_file(&autosave_file) {
+                error!("Failed to save state: {}", e);
+            }
+        }
+    });
+
+    // Ctrl+C で終了する場合も状態を保存する
+    tokio::select! {
+        result = start_socketio_server(port) => {
+            result.map_err(|e| format!("サーバーを起動できませんでした: {}", e))?;
+        }
+        _ = tokio::signal::ctrl_c() => {
+            info!("Shutting down");
+        }
+    }
+    save_state(&state_file)
+}
+
+fn create(args: &Args) -> Result<(), String> {
+    let state_file = args.state_file();
+    let _lock = lock_state(&state_file)?;
+    load_state(&state_file)?;
+
+    let participants = match args.get("participants") {
+        Some(path) => read_participants(path)?,
+        None => Vec::new(),
+    };
+    let nolist = participants.is_empty();
+    let event: Eventstruct = serde_json::from_value(json!({
+        "eventname": args.require("name")?,
+        "eventinfo": args.get("info").unwrap_or_default(),
+        "participants": participants,
+        "arrowtoday": args.flags.iter().any(|flag| flag == "allow-ontheday"),
+        "autotodayregister": false,
+        "nolist": nolist,
+        "soukai": false,
+        "issue_join_code": args.flags.iter().any(|flag| flag == "join-code"),
+        "self_checkin": args.flags.iter().any(|flag| flag == "self-checkin"),
+        "qr_checkin": args.flags.iter().any(|flag| flag == "qr-checkin"),
+    }))
+    .map_err(|e| format!("イベントを作成できませんでした: {}", e))?;
+
+    let uuid = create_event(event);
+    save_state(&state_file)?;
+    println!("{}", uuid);
+    if let Some(code) = get_event_store().read(&uuid, |state| state.event.join_code().map(str::to_string)).flatten() {
+        println!("参加コード: {}", code);
+    }
+    Ok(())
+}
+
+fn import(args: &Args) -> Result<(), String> {
+    let state_file = args.state_file();
+    let _lock = lock_state(&state_file)?;
+    load_state(&state_file)?;
+
+    let uuid = args.require("event")?;
+    let participants = read_participants(args.require("participants")?)?;
+    let added = get_event_store()
+        .update(uuid, |state| state.add_participants(&participants))
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+    save_state(&state_file)?;
+    println!("{}人を名簿に追加しました（{}人は登録済み）", added, participants.len() - added);
+    Ok(())
+}
+
+fn import_cards(args: &Args) -> Result<(), String> {
+    let state_file = args.state_file();
+    let _lock = lock_state(&state_file)?;
+    load_state(&state_file)?;
+
+    let uuid = args.require("event")?;
+    let path = args.require("cards")?;
+    let csv = std::fs::read_to_string(path).map_err(|e| format!("カード番号のファイルを読み込めませんでした: {}", e))?;
+    let replace = args.flags.iter().any(|flag| flag == "replace");
+    let result = get_event_store()
+        .update(uuid, |state| state.import_card_ids(&csv, replace))
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+    save_state(&state_file)?;
+    println!("{}件を読み込みました（{}件は名簿にないため読み込みませんでした）", result.imported, result.skipped);
+    Ok(())
+}
+
+fn export(args: &Args) -> Result<(), String> {
+    load_state(&args.state_file())?;
+
+    let uuid = args.require("event")?;
+    let csv = get_event_store()
+        .read(uuid, |state| {
+            let session = args.get("session").map(str::to_string).or_else(|| state.active_session());
+            state.export_csv(session.as_deref())
+        })
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+
+    match args.get("output") {
+        Some(path) => std::fs::write(path, csv).map_err(|e| format!("ファイルに書き出せませんでした: {}", e)),
+        None => {
+            print!("{}", csv);
+            Ok(())
+        }
+    }
+}
+
+fn list(args: &Args) -> Result<(), String> {
+    load_state(&args.state_file())?;
+
+    let store = get_event_store();
+    let mut ids = store.ids();
+    ids.sort();
+    for uuid in ids {
+        let Some(event) = store.read(&uuid, |state| serde_json::to_value(&state.event)) else {
+            continue;
+        };
+        let event = event.map_err(|e| format!("イベントを読み込めませんでした: {}", e))?;
+        println!(
+            "{}\t{}\t{}人",
+            uuid,
+            event["eventname"].as_str().unwrap_or_default(),
+            event["participants"].as_array().map_or(0, Vec::len)
+        );
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    init_stderr_logging();
+
+    let result = match Args::parse() {
+        Ok(args) => match args.command.as_str() {
+            "serve" => serve(&args).await,
+            "create" => create(&args),
+            "import" => import(&args),
+            "import-cards" => import_cards(&args),
+            "export" => export(&args),
+            "list" => list(&args),
+            _ => Err(format!("不明なコマンドです: {}\n\n{}", args.command, USAGE)),
+        },
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_file_cannot_be_locked_twice() {
+        let path = std::env::temp_dir().join(format!("list-checker-lock-{}.json", std::process::id()));
+        let lock = lock_state(&path).unwrap();
+        assert!(lock_state(&path).is_err());
+
+        drop(lock);
+        let relocked = lock_state(&path).is_ok();
+        let mut lock_path = path.into_os_string();
+        lock_path.push(".lock");
+        std::fs::remove_file(lock_path).unwrap();
+        assert!(relocked);
+    }
+}
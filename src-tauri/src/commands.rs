@@ -0,0 +1,471 @@
+// デスクトップアプリ（Tauri）から呼び出すコマンド
+// ヘッドレスサーバー（list-checker-server）はTauriを使わないため、desktop フィーチャー有効時のみビルドする
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "debug")]
+use tauri::State;
+#[cfg(feature = "debug")]
+use std::sync::Arc;
+use chrono::Local;
+use log::{error, info, warn};
+
+use crate::*;
+
+#[tauri::command]
+fn register_event(data: String) -> String {
+
+    let parsed_data: Eventstruct = match serde_json::from_str(&data) {
+        Ok(event) => event,
+        Err(e) => {
+            error!("Failed to parse event data: {}", e);
+            return String::new(); // Return an empty string on error
+        }
+    };
+
+    create_event(parsed_data)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JsonToAttendeesStruct {
+    attendeeindex: Vec<i32>,
+    uuid: String,
+}
+
+#[tauri::command]
+fn json_to_attendees(data: JsonToAttendeesStruct) -> String {
+    info!("Received json_to_attendees for {}: {}", data.uuid, redact_ids(&data.attendeeindex));
+
+    //既存のデータは取得しない
+    get_event_store().update(&data.uuid, |state| {
+        state.current_session_mut().attendees = data.attendeeindex.clone();
+        state.reset_changes();
+    });
+
+    info!("Updated attendees for {}: {}", data.uuid, redact_ids(&data.attendeeindex));
+
+    // 参加者の情報をクライアントに送信
+    let json = serde_json::to_string(&data).unwrap();
+    json
+}
+
+
+#[tauri::command]
+fn json_to_today(data: JsonToTodayStruct) -> String {
+    info!("Received json_to_today for {}: {}", data.uuid, redact_ids(&data.today));
+
+    //既存のデータは取得しない
+    get_event_store().update(&data.uuid, |state| {
+        state.current_session_mut().ontheday = data.today.clone();
+        state.reset_changes();
+    });
+
+    info!("Updated today for {}: {}", data.uuid, redact_ids(&data.today));
+    // 今日の情報をクライアントに送信
+    let json = serde_json::to_string(&data).unwrap();
+    json
+}
+
+
+
+
+
+#[tauri::command]
+fn get_event(uuid: String, ) -> Option<Eventstruct> {
+    get_event_store().read(&uuid, |state| state.event.clone())
+}
+
+#[tauri::command]
+fn get_all_events() -> Vec<Eventstruct> {
+    let store = get_event_store();
+
+    store
+        .ids()
+        .iter()
+        .filter_map(|uuid| {
+            store.read(uuid, |state| {
+                let mut event = state.event.clone();
+                // イベントIDをroomidに設定
+                event.roomid = Some(uuid.clone());
+                event
+            })
+        })
+        .collect()
+}
+
+#[tauri::command]
+fn get_local_ip() -> String {
+    match local_ip_address::local_ip() {
+        Ok(ip) => ip.to_string(),
+        Err(e) => {
+            error!("Failed to get local IP address: {}", e);
+            "Error".to_string()
+        }
+    }
+}
+
+
+#[cfg(feature = "debug")]
+#[tauri::command]
+fn debug_hashmap (state: State<Arc<EventStore>>) -> String {
+    let mut output = String::new();
+    
+    for uuid in state.ids() {
+        if let Some(event_state) = state.get(&uuid) {
+            output.push_str(&format!("Key: {}, Value: {:?}\n", uuid, event_state));
+        }
+    }
+    
+    output
+
+}
+
+#[cfg(feature = "debug")]
+#[tauri::command]
+async fn debug_run_server() -> String {
+    let port = SOCKET_PORT;
+    match start_socketio_server(port).await {
+        Ok(_) => format!("Socket.IO server started on port {}", port),
+        Err(e) => format!("Failed to start Socket.IO server: {}", e),
+    }
+    
+}
+
+// Socket.IOサーバーを起動する（起動済みの場合は何もしない）
+// ポートの確保までを待ち、以降はバックグラウンドで動作させる
+#[tauri::command]
+async fn start_server() -> Result<String, String> {
+    let port = SOCKET_PORT;
+    // 同時に呼ばれても二重にポートを確保しないよう、確認と同時に起動済みにする
+    {
+        let mut is_running = IS_SERVER_RUNNING.lock().unwrap();
+        if *is_running {
+            return Ok(format!("Socket.IO server is already running on port {}", port));
+        }
+        *is_running = true;
+    }
+
+    let listeners = match bind_socketio_server(port).await {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            *IS_SERVER_RUNNING.lock().unwrap() = false;
+            return Err(format!("サーバーを起動できませんでした: {}", e));
+        }
+    };
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = serve_socketio(listeners).await {
+            error!("Socket.IO server stopped: {}", e);
+            *IS_SERVER_RUNNING.lock().unwrap() = false;
+        }
+    });
+    Ok(format!("Socket.IO server started on port {}", port))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AttendeeIndex {
+    attendeeindex: Vec<i32>,
+    uuid: String,
+}
+
+
+
+#[tauri::command]
+async fn register_attendees(data: AttendeeIndex) -> Result<String, String> {
+    info!("Received register_attendees for {}: {}", data.uuid, redact_ids(&data.attendeeindex));
+
+    // 新しい参加者を追加（重複を避け、昇順にソート）
+    let registered = get_event_store().update(&data.uuid, |state| {
+        // 端末からの登録と同じく、受付時間外は登録しない
+        if let Some(reason) = state.window_state().rejection_reason() {
+            warn!("Rejected register_attendees outside check-in window for {}: {}", data.uuid, reason);
+            return Err(reason.to_string());
+        }
+        let version = state.version;
+        let (new_ids, _) = state.register_attendees(&data.attendeeindex);
+        get_metrics().record_checkins(&data.uuid, new_ids.len());
+        for id in &new_ids {
+            state.push_log("info", format!("出席登録: {} が出席しました", id));
+        }
+        Ok((state.current_session().attendees, state.sync_since(version)))
+    });
+    let (attendees, sync) = registered.ok_or_else(|| EVENT_NOT_FOUND.to_string())??;
+
+    info!("Updated attendees for {}: {}", data.uuid, redact_ids(&attendees));
+
+    // 参加者の差分をクライアントに送信
+    broadcast_attendance(&data.uuid, &sync).await;
+
+    Ok("Attendees registered successfully".to_string())
+
+}
+
+#[tauri::command]
+async fn add_session(uuid: String, date: String, label: String) -> Result<Session, String> {
+    let session = Session {
+        id: new_session_id(),
+        date,
+        label,
+    };
+
+    let activated = get_event_store()
+        .update(&uuid, |state| state.add_session(session.clone()))
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+
+    info!("Session added to {}: {:?}", uuid, session);
+    // 最初のセッションはアクティブになるため、接続中の端末に切り替えを伝える
+    if activated {
+        broadcast_active_session(&uuid, &session).await;
+    }
+    Ok(session)
+}
+
+#[tauri::command]
+async fn set_active_session(uuid: String, session_id: String) -> Result<Session, String> {
+    let session = get_event_store().try_update(&uuid, |state| state.set_active_session(&session_id))?;
+    broadcast_active_session(&uuid, &session).await;
+    Ok(session)
+}
+
+#[tauri::command]
+fn get_devices(uuid: String) -> Option<Vec<DeviceInfo>> {
+    get_event_store().read(&uuid, |state| state.devices())
+}
+
+#[tauri::command]
+async fn admin_kick_device(uuid: String, socket_id: String) -> Result<DeviceInfo, String> {
+    kick_device(&uuid, &socket_id).await
+}
+
+#[tauri::command]
+async fn admin_lock_device(uuid: String, socket_id: String, locked: bool) -> Result<DeviceInfo, String> {
+    lock_device(&uuid, &socket_id, locked).await
+}
+
+#[tauri::command]
+async fn admin_ban_ip(uuid: String, ip: String) -> Result<Vec<String>, String> {
+    ban_ip(&uuid, &ip).await
+}
+
+#[tauri::command]
+async fn send_announcement(
+    uuid: String,
+    message: String,
+    severity: Severity,
+    duration_secs: Option<u64>,
+) -> Result<Announcement, String> {
+    announce(&uuid, Announcement { message, severity, duration_secs }).await
+}
+
+#[tauri::command]
+async fn regenerate_join_code(uuid: String) -> Result<String, String> {
+    reissue_join_code(&uuid).await
+}
+
+#[tauri::command]
+async fn create_device_token(
+    uuid: String,
+    device_name: String,
+    role: DeviceRole,
+    valid_hours: Option<i64>,
+) -> Result<DeviceToken, String> {
+    issue_device_token(&uuid, &device_name, role, valid_hours).await
+}
+
+#[tauri::command]
+fn get_device_tokens(uuid: String) -> Option<Vec<DeviceToken>> {
+    get_event_store().read(&uuid, |state| state.device_tokens())
+}
+
+#[tauri::command]
+async fn delete_device_token(uuid: String, token_id: String) -> Result<DeviceToken, String> {
+    revoke_device_token(&uuid, &token_id).await
+}
+
+#[tauri::command]
+fn require_device_token(uuid: String, required: bool) -> Result<(), String> {
+    set_require_device_token(&uuid, required)
+}
+
+#[tauri::command]
+fn set_self_checkin(uuid: String, enabled: bool) -> Result<(), String> {
+    enable_self_checkin(&uuid, enabled)
+}
+
+// モニタ（アプリ）に表示するセルフ出席登録のコード
+#[tauri::command]
+fn get_self_checkin_code(uuid: String) -> Option<SelfCheckinCode> {
+    get_event_store().read(&uuid, current_self_checkin_code).flatten()
+}
+
+#[tauri::command]
+fn set_qr_checkin(uuid: String, enabled: bool) -> Result<(), String> {
+    enable_qr_checkin(&uuid, enabled)
+}
+
+// モニタ（アプリ）に表示するQRコードのトークン
+#[tauri::command]
+fn get_qr_token(uuid: String) -> Option<QrCheckinToken> {
+    get_event_store().read(&uuid, current_qr_token).flatten()
+}
+
+// 学生証の番号と参加者IDの対応表（CSV）を読み込む
+#[tauri::command]
+async fn upload_card_ids(uuid: String, csv: String, replace: bool) -> Result<CardImport, String> {
+    import_card_ids(&uuid, &csv, replace).await
+}
+
+#[tauri::command]
+fn get_attendance_totals(uuid: String) -> Option<Vec<AttendanceTotal>> {
+    get_event_store().read(&uuid, |state| state.attendance_totals())
+}
+
+#[tauri::command]
+async fn check_out(uuid: String, ids: Vec<String>) -> Result<Vec<String>, String> {
+    let (checked_out, logs) = get_event_store()
+        .update(&uuid, |state| record_check_out(state, &ids))
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+    broadcast_logs(&uuid, &logs).await;
+    broadcast_check_out(&uuid, &checked_out).await;
+    Ok(checked_out)
+}
+
+#[tauri::command]
+fn set_checkin_window(uuid: String, window: Option<CheckinWindow>) -> Result<WindowState, String> {
+    // 状態の変化はサーバーの監視タスクがroomに通知する
+    get_event_store()
+        .update(&uuid, |state| {
+            state.event.checkin_window = window;
+            state.window_state()
+        })
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())
+}
+
+#[tauri::command]
+fn set_arrival_policy(uuid: String, policy: Option<ArrivalPolicy>) -> Result<(), String> {
+    get_event_store()
+        .update(&uuid, |state| state.event.arrival_policy = policy)
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())
+}
+
+#[tauri::command]
+fn set_max_ontheday(uuid: String, limit: Option<usize>) -> Result<(), String> {
+    get_event_store()
+        .update(&uuid, |state| state.event.max_ontheday = limit)
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())
+}
+
+#[tauri::command]
+fn get_arrival_statistics(uuid: String, session_id: Option<String>) -> Option<ArrivalStatistics> {
+    get_event_store().read(&uuid, |state| {
+        let session = session_id.or_else(|| state.active_session());
+        state.arrival_statistics(session.as_deref())
+    })
+}
+
+#[tauri::command]
+fn export_attendance(uuid: String, session_id: Option<String>) -> Result<String, String> {
+    get_event_store()
+        .read(&uuid, |state| {
+            let session = session_id.or_else(|| state.active_session());
+            state.export_csv(session.as_deref())
+        })
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())
+}
+
+// 保存されているログをファイルに書き出す（保存先はダイアログで選択、キャンセル時は None）
+#[tauri::command]
+async fn export_logs(app: tauri::AppHandle) -> Result<Option<String>, String> {
+    use tauri_plugin_dialog::DialogExt;
+
+    let logs = collect_logs(&log_dir(&app)?)?;
+    let file_name = format!("list-checker-log-{}.log", Local::now().format("%Y%m%d-%H%M%S"));
+    let Some(path) = app.dialog().file().set_file_name(file_name).add_filter("ログ", &["log", "txt"]).blocking_save_file() else {
+        return Ok(None);
+    };
+    let path = path.into_path().map_err(|e| format!("保存先が正しくありません: {}", e))?;
+    std::fs::write(&path, logs).map_err(|e| format!("ログを書き出せませんでした: {}", e))?;
+    info!("Exported logs to {}", path.display());
+    Ok(Some(path.display().to_string()))
+}
+
+// ログの保存先フォルダを開く
+#[tauri::command]
+fn open_log_folder(app: tauri::AppHandle) -> Result<(), String> {
+    use tauri_plugin_opener::OpenerExt;
+
+    let dir = log_dir(&app)?;
+    app.opener()
+        .open_path(dir.display().to_string(), None::<&str>)
+        .map_err(|e| format!("ログの保存先を開けませんでした: {}", e))
+}
+
+#[tauri::command]
+fn server_check() -> bool {
+    let is_running = IS_SERVER_RUNNING.lock().unwrap();
+    *is_running
+}
+
+
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    use tauri::Manager;
+    
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
+        .manage(get_event_store())
+        .invoke_handler(tauri::generate_handler![
+            register_event, get_event, get_all_events, start_server, register_attendees, get_local_ip , json_to_attendees, json_to_today, server_check,
+            add_session, set_active_session, get_attendance_totals, check_out, export_attendance,
+            set_checkin_window, set_arrival_policy, get_arrival_statistics, get_devices,
+            admin_kick_device, admin_lock_device, admin_ban_ip, send_announcement,
+            set_max_ontheday, export_logs, open_log_folder, regenerate_join_code,
+            create_device_token, get_device_tokens, delete_device_token, require_device_token,
+            set_self_checkin, get_self_checkin_code, set_qr_checkin, get_qr_token,
+            upload_card_ids,
+            #[cfg(feature = "debug")]
+            debug_hashmap,
+            #[cfg(feature = "debug")]
+            debug_run_server
+        ])
+        .setup(|app| {
+            if let Err(e) = init_logging(app.handle()) {
+                eprintln!("Failed to initialize logging: {}", e);
+            }
+
+            #[cfg(desktop)]
+            {
+                let handle = app.handle().clone();
+                
+                if let Some(window) = app.get_webview_window("main") {
+                    window.on_window_event(move |event| {
+                        if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                            let handle_clone = handle.clone();
+                            
+                            // デフォルトの閉じる動作を防ぐ
+                            api.prevent_close();
+                            
+                            // 確認ダイアログを表示
+                            tauri::async_runtime::spawn(async move {
+                                use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
+                                
+                                let answer = handle_clone.dialog()
+                                    .message("アプリケーションを終了しますか？")
+                                    .title("終了確認")
+                                    .kind(MessageDialogKind::Warning)
+                                    .blocking_show();
+                                
+                                if answer {
+                                    // ユーザーが「終了」を選択
+                                    handle_clone.exit(0);
+                                }
+                            });
+                        }
+                    });
+                }
+            }
+            
+            Ok(())
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
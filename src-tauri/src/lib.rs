@@ -1,143 +1,207 @@
 use serde::{ Deserialize, Serialize, Deserializer};
 use serde::de::{self, Visitor};
 use uuid::Uuid;
-use std::collections::HashMap;
-use tauri::State;
-use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::Mutex;
 use std::fmt;
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone};
+use log::{error, info};
 
 
 
 
+#[cfg(feature = "desktop")]
+mod commands;
+pub mod logging;
 pub mod socket;
+pub mod store;
 
+#[cfg(feature = "desktop")]
+pub use commands::run;
+pub use logging::*;
 pub use socket::*;
+pub use store::*;
 
 
 static IS_SERVER_RUNNING: Mutex<bool> = Mutex::new(false);
 
 
-#[derive(Debug, Default)]
-pub struct AppState {
-    store: Mutex<HashMap<String, Eventstruct>>,
-}
-pub struct AppState2 {
-    store: Mutex<HashMap<String, Vec<i32>>>,
-}
-
-pub struct AppState3 {
-    store: Mutex<HashMap<String, Vec<String>>>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub arrowtoday: bool,
+    pub autotodayregister: bool,
+    pub soukai: bool,
+    pub nolist: bool,
+    #[serde(default)]
+    pub requirecheckout: bool,
 }
 
-pub struct AppState4 {
-    store: Mutex<HashMap<String, Settings>>,
+// 入室から退室までの1区間（時刻はRFC3339）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interval {
+    pub check_in: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_out: Option<String>,
 }
 
-impl AppState {
-    pub fn new() -> Self {
-        Self::default()
-    }
-    
-    pub fn insert(&self, key: String, value: Eventstruct) {
-        let mut store = self.store.lock().unwrap();
-        store.insert(key, value);
-    }
-    
-    pub fn get(&self, key: &str) -> Option<Eventstruct> {
-        let store = self.store.lock().unwrap();
-        store.get(key).cloned()
-    }
+// 参加者ごとの入退室記録（:attendees と同じセッション単位で保存）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AttendanceRecord {
+    pub intervals: Vec<Interval>,
+    #[serde(default)]
+    pub arrival: ArrivalCategory,
+    // オフライン中に端末で受け付け、再接続時に反映した記録
+    #[serde(default)]
+    pub offline: bool,
 }
 
-impl AppState2 {
-    pub fn new() -> Self {
+impl AttendanceRecord {
+    pub fn checked_in(at: &str, arrival: ArrivalCategory, offline: bool) -> Self {
         Self {
-            store: Mutex::new(HashMap::new()),
+            intervals: vec![Interval {
+                check_in: at.to_string(),
+                check_out: None,
+            }],
+            arrival,
+            offline,
         }
     }
-    
-    pub fn insert(&self, key: String, value: Vec<i32>) {
-        let mut store = self.store.lock().unwrap();
-        store.insert(key, value);
-    }
-    
-    pub fn get(&self, key: &str) -> Option<Vec<i32>> {
-        let store = self.store.lock().unwrap();
-        store.get(key).cloned()
+
+    // 最後の区間が閉じていなければ在室中
+    pub fn is_present(&self) -> bool {
+        self.intervals.last().is_some_and(|interval| interval.check_out.is_none())
     }
-    
-}
 
-impl AppState3 {
-    pub fn new() -> Self {
-        Self {
-            store: Mutex::new(HashMap::new()),
-        }
+    pub fn is_checked_out(&self) -> bool {
+        !self.intervals.is_empty() && !self.is_present()
     }
-    
-    pub fn insert(&self, key: String, value: Vec<String>) {
-        let mut store = self.store.lock().unwrap();
-        store.insert(key, value);
+
+    // 在室時間の合計（在室中の区間は現在時刻まで）
+    pub fn total_seconds(&self) -> i64 {
+        let now = Local::now().fixed_offset();
+        self.intervals
+            .iter()
+            .filter_map(|interval| {
+                let check_in = DateTime::parse_from_rfc3339(&interval.check_in).ok()?;
+                let check_out = match &interval.check_out {
+                    Some(check_out) => DateTime::parse_from_rfc3339(check_out).ok()?,
+                    None => now,
+                };
+                Some((check_out - check_in).num_seconds().max(0))
+            })
+            .sum()
     }
-    
-    pub fn get(&self, key: &str) -> Option<Vec<String>> {
-        let store = self.store.lock().unwrap();
-        store.get(key).cloned()
+}
+
+// RFC3339 または "YYYY-MM-DDTHH:MM[:SS]"（ローカル時刻）を受け付ける
+pub fn parse_local_datetime(value: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some(datetime);
     }
+
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .map(|datetime| datetime.fixed_offset())
 }
 
-impl AppState4 {
-    pub fn new() -> Self {
-        Self {
-            store: Mutex::new(HashMap::new()),
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowState {
+    NotYetOpen,
+    Open,
+    Closed,
+}
+
+impl WindowState {
+    // 受付時間外の場合の拒否理由
+    pub fn rejection_reason(&self) -> Option<&'static str> {
+        match self {
+            WindowState::NotYetOpen => Some("受付開始前のため登録できません"),
+            WindowState::Closed => Some("受付は終了しました"),
+            WindowState::Open => None,
         }
     }
-    
-    pub fn insert(&self, key: String, value: Settings) {
-        let mut store = self.store.lock().unwrap();
-        store.insert(key, value);
-    }
-    
-    pub fn get(&self, key: &str) -> Option<Settings> {
-        let store = self.store.lock().unwrap();
-        store.get(key).cloned()
-    }
 }
 
-static APP_STATE: OnceLock<Arc<AppState>> = OnceLock::new();
-static APP_STATE2: OnceLock<Arc<AppState2>> = OnceLock::new();
-static APP_STATE3: OnceLock<Arc<AppState3>> = OnceLock::new();
-static APP_STATE4: OnceLock<Arc<AppState4>> = OnceLock::new();
-
-pub fn get_app_state() -> Arc<AppState> {
-    APP_STATE.get_or_init(|| Arc::new(AppState::new())).clone()
+// 受付時間（開始・終了・遅刻扱いの開始時刻）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckinWindow {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opens_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closes_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub late_after: Option<String>,
 }
 
-pub fn get_app_state2() -> Arc<AppState2> {
-    APP_STATE2.get_or_init(|| Arc::new(AppState2::new())).clone()
+impl CheckinWindow {
+    pub fn state_at(&self, now: DateTime<FixedOffset>) -> WindowState {
+        let opens_at = self.opens_at.as_deref().and_then(parse_local_datetime);
+        let closes_at = self.closes_at.as_deref().and_then(parse_local_datetime);
+
+        if opens_at.is_some_and(|opens_at| now < opens_at) {
+            WindowState::NotYetOpen
+        } else if closes_at.is_some_and(|closes_at| now >= closes_at) {
+            WindowState::Closed
+        } else {
+            WindowState::Open
+        }
+    }
+
+    pub fn is_late(&self, at: DateTime<FixedOffset>) -> bool {
+        self.late_after
+            .as_deref()
+            .and_then(parse_local_datetime)
+            .is_some_and(|late_after| at >= late_after)
+    }
 }
-pub fn get_app_state3() -> Arc<AppState3> {
-    APP_STATE3.get_or_init(|| Arc::new(AppState3::new())).clone()
+
+// 到着区分（定刻・遅刻・欠席扱い）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArrivalCategory {
+    #[default]
+    OnTime,
+    Late,
+    Absent,
 }
 
-pub fn get_app_state4() -> Arc<AppState4> {
-    APP_STATE4.get_or_init(|| Arc::new(AppState4::new())).clone()
+impl ArrivalCategory {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ArrivalCategory::OnTime => "定刻",
+            ArrivalCategory::Late => "遅刻",
+            ArrivalCategory::Absent => "欠席扱い",
+        }
+    }
 }
 
+// 開始時刻からの経過時間で到着区分を判定する基準
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Settings {
-    pub arrowtoday: bool,
-    pub autotodayregister: bool,
-    pub soukai: bool,
-    pub nolist: bool,
+pub struct ArrivalPolicy {
+    pub starts_at: String,
+    #[serde(default)]
+    pub late_after_minutes: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub absent_after_minutes: Option<i64>,
 }
 
-// Participant構造体（オブジェクト形式用）
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ParticipantObject {
-    id: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    attended: Option<bool>,
+impl ArrivalPolicy {
+    pub fn classify(&self, at: DateTime<FixedOffset>) -> ArrivalCategory {
+        let Some(starts_at) = parse_local_datetime(&self.starts_at) else {
+            return ArrivalCategory::OnTime;
+        };
+        let elapsed = (at - starts_at).num_seconds();
+
+        if self.absent_after_minutes.is_some_and(|minutes| elapsed > minutes * 60) {
+            ArrivalCategory::Absent
+        } else if elapsed > self.late_after_minutes * 60 {
+            ArrivalCategory::Late
+        } else {
+            ArrivalCategory::OnTime
+        }
+    }
 }
 
 // カスタムデシリアライザ：文字列配列またはオブジェクト配列を受け入れる
@@ -179,6 +243,16 @@ where
     deserializer.deserialize_seq(ParticipantsVisitor)
 }
 
+// セッション（同じ名簿で複数回実施するイベントの各回）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(default)]
+    pub id: String,
+    pub date: String,
+    #[serde(default)]
+    pub label: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Eventstruct {
     eventname: String,
@@ -191,259 +265,218 @@ pub struct Eventstruct {
     autotodayregister: bool,
     nolist: bool,
     soukai: bool,
+    #[serde(default)]
+    requirecheckout: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     roomid: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     password: Option<String>,
+    #[serde(default)]
+    sessions: Vec<Session>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    active_session: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checkin_window: Option<CheckinWindow>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arrival_policy: Option<ArrivalPolicy>,
+    // 当日参加者の上限（未設定の場合は DEFAULT_MAX_ONTHEDAY）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_ontheday: Option<usize>,
+    // 読み上げやすい参加コード（issue_join_code を指定して作成した場合のみ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    join_code: Option<String>,
+    #[serde(default, skip_serializing)]
+    issue_join_code: bool,
+    // 端末トークンを持たない端末の参加を禁止する
+    #[serde(default)]
+    require_device_token: bool,
+    // 学生が自分のスマートフォンから出席登録できる（モニタに表示したコードの入力が必要）
+    #[serde(default)]
+    self_checkin: bool,
+    // モニタに表示したQRコードを読み取った端末からのみ出席登録できる（主催者が用意した端末を除く）
+    #[serde(default)]
+    qr_checkin: bool,
+    // カード番号の対応表が登録されている（端末は名簿にない入力もサーバーに送る）
+    #[serde(default, skip_deserializing)]
+    card_reader: bool,
 }
-    
-#[tauri::command]
-fn register_event(data: String) -> String {
-
-    let mut parsed_data: Eventstruct = match serde_json::from_str(&data) {
-        Ok(event) => event,
-        Err(e) => {
-            eprintln!("Failed to parse event data: {}", e);
-            return String::new(); // Return an empty string on error
-        }
-    };
-
-
-    let uuid = Uuid::new_v4().to_string();
-    let uuid = uuid.split('-').next().unwrap_or(&uuid).to_string();
-    let event_key = format!("{}:datas", uuid);
-
-    // roomidを設定
-    parsed_data.roomid = Some(uuid.clone());
-    parsed_data.password = Some(String::new()); // 空のパスワード
-
-    let app_state = get_app_state();
 
-    // todaylistがある場合、Socket.IOサーバーに保存
-    if let Some(ref todaylist) = parsed_data.todaylist {
-        if !todaylist.is_empty() {
-            let ontheday_key = format!("{}:ontheday", uuid);
-            let app_state3 = get_app_state3();
-            app_state3.insert(ontheday_key, todaylist.clone());
-            println!("Saved todaylist with {} entries", todaylist.len());
-        }
+impl Eventstruct {
+    pub fn join_code(&self) -> Option<&str> {
+        self.join_code.as_deref()
     }
+}
 
-    app_state.insert(event_key.clone(), parsed_data);
-
-    
-
-    println!("Event registered with key: {}", event_key);
-
-    uuid
+fn new_session_id() -> String {
+    let id = Uuid::new_v4().to_string();
+    id.split('-').next().unwrap_or(&id).to_string()
 }
 
+// 参加者ごとの累計出席回数
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct JsonToAttendeesStruct {
-    attendeeindex: Vec<i32>,
-    uuid: String,
+pub struct AttendanceTotal {
+    pub id: String,
+    pub attended: usize,
+    pub late: usize,
+    pub sessions: usize,
+    pub ontheday: bool,
 }
 
-#[tauri::command]
-fn json_to_attendees(data: JsonToAttendeesStruct) -> String {
-    println!("Received register_attendees: {:?}", data);
-    let app_state = get_app_state2();
-    let key = format!("{}:attendees", data.uuid);
-
-    //既存のデータは取得しない
-    app_state.insert(key.clone(), data.attendeeindex.clone());
-
-    
-    println!("Updated attendees for {}: {:?}", data.uuid, data.attendeeindex);
+// セッションごとの到着区分の集計
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArrivalStatistics {
+    pub on_time: usize,
+    pub late: usize,
+    pub absent: usize,
+}
 
-    // 参加者の情報をクライアントに送信
-    let json = serde_json::to_string(&data).unwrap();
-    json
+fn new_event_id() -> String {
+    let uuid = Uuid::new_v4().to_string();
+    uuid.split('-').next().unwrap_or(&uuid).to_string()
 }
 
+// 参加コードに使う文字（0/O・1/I など読み間違えやすい文字を除いた32文字）
+const JOIN_CODE_CHARS: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const JOIN_CODE_LENGTH: usize = 6;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct JsonToTodayStruct {
-    uuid: String,
-    today: Vec<String>,
+fn new_join_code() -> String {
+    // 文字の種類が32なので、乱数の下位5ビットで偏りなく選べる
+    Uuid::new_v4().as_bytes()[..JOIN_CODE_LENGTH]
+        .iter()
+        .map(|byte| JOIN_CODE_CHARS[(byte & 31) as usize] as char)
+        .collect()
 }
 
-#[tauri::command]
-fn json_to_today(data: JsonToTodayStruct) -> String {
-    println!("Received register_today: {:?}", data);
-    let app_state = get_app_state3();
-    let key =  format!("{}:ontheday", data.uuid);
-
-    //既存のデータは取得しない
-    app_state.insert(key.clone(), data.today.clone());
-    println!("Updated today for {}: {:?}", data.uuid, data.today);
-    // 今日の情報をクライアントに送信
-    let json = serde_json::to_string(&data).unwrap();
-    json
+// 参加コードを発行し直す（漏れた場合などに使う。接続中の端末はそのまま）
+pub fn issue_join_code(uuid: &str) -> Result<String, String> {
+    let code = get_event_store()
+        .issue_join_code(uuid, new_join_code)
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+    info!("Issued join code for {}", uuid);
+    Ok(code)
 }
 
+// イベントをストアに登録し、イベントIDを返す
+pub fn create_event(mut parsed_data: Eventstruct) -> String {
+    parsed_data.password = Some(String::new()); // 空のパスワード
 
-
-
-
-#[tauri::command]
-fn get_event(uuid: String, ) -> Option<Eventstruct> {
-    let app_state = get_app_state();
-
-
-    app_state.get(&format!("{}:datas", uuid))
-}
-
-#[tauri::command]
-fn get_all_events() -> Vec<Eventstruct> {
-    let app_state = get_app_state();
-    let store = app_state.store.lock().unwrap();
-    
-    let mut events = Vec::new();
-    for (key, value) in store.iter() {
-        if key.ends_with(":datas") {
-            let mut event = value.clone();
-            // キーからUUIDを抽出してroomidに設定
-            if let Some(uuid) = key.strip_suffix(":datas") {
-                event.roomid = Some(uuid.to_string());
-            }
-            events.push(event);
+    // IDのないセッションにIDを割り当て、最初のセッションをアクティブにする
+    for session in parsed_data.sessions.iter_mut() {
+        if session.id.is_empty() {
+            session.id = new_session_id();
         }
     }
-    
-    events
-}
+    parsed_data.active_session = parsed_data.sessions.first().map(|session| session.id.clone());
+
+    let mut state = EventState::new(parsed_data);
 
-#[tauri::command]
-fn get_local_ip() -> String {
-    match local_ip_address::local_ip() {
-        Ok(ip) => ip.to_string(),
-        Err(e) => {
-            eprintln!("Failed to get local IP address: {}", e);
-            "Error".to_string()
+    // todaylistがある場合、Socket.IOサーバーに保存
+    if let Some(todaylist) = state.event.todaylist.clone() {
+        if !todaylist.is_empty() {
+            state.current_session_mut().ontheday = todaylist.clone();
+            info!("Saved todaylist with {} entries", todaylist.len());
         }
     }
-}
 
-
-#[tauri::command]
-fn debug_hashmap (state: State<AppState>) -> String {
-    let store = state.store.lock().unwrap();
-    let mut output = String::new();
-    
-    for (key, value) in store.iter() {
-        output.push_str(&format!("Key: {}, Value: {:?}\n", key, value));
+    // 入力しやすいようにUUIDの先頭8文字をイベントIDとする（既存のイベントと重複した場合は作り直す）
+    let issue = state.event.issue_join_code;
+    let uuid = get_event_store().insert_new(new_event_id, state);
+    if issue {
+        if let Err(e) = issue_join_code(&uuid) {
+            error!("Failed to issue join code: {}", e);
+        }
     }
-    
-    output
 
-}
+    info!("Event registered with id: {}", uuid);
 
-#[tauri::command]
-async fn debug_run_server() -> String {
-    let port = 50345;
-    match start_socketio_server(port).await {
-        Ok(_) => format!("Socket.IO server started on port {}", port),
-        Err(e) => format!("Failed to start Socket.IO server: {}", e),
-    }
-    
+    uuid
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct AttendeeIndex {
-    attendeeindex: Vec<i32>,
+pub struct JsonToTodayStruct {
     uuid: String,
+    today: Vec<String>,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn at(value: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(value).unwrap()
+    }
 
-#[tauri::command]
-fn register_attendees(data: AttendeeIndex) -> String {
-    println!("Received register_attendees: {:?}", data);
-
-    let app_state = get_app_state2();
-    let key = format!("{}:attendees", data.uuid);
-
-    // 既存のデータを取得
-    let mut existing_attendees = app_state.get(&key).unwrap_or_else(|| vec![]);
-
-    // 新しい参加者を追加（重複を避ける）
-    for &index in &data.attendeeindex {
-        if !existing_attendees.contains(&index) {
-            existing_attendees.push(index);
+    fn window() -> CheckinWindow {
+        CheckinWindow {
+            opens_at: Some("2026-04-01T09:00:00+09:00".to_string()),
+            closes_at: Some("2026-04-01T10:00:00+09:00".to_string()),
+            late_after: Some("2026-04-01T09:10:00+09:00".to_string()),
         }
     }
 
-    //昇順にソート
-    existing_attendees.sort_unstable();
-
-    // 更新されたデータを保存
-    app_state.insert(key, existing_attendees.clone());
-
-    println!("Updated attendees for {}: {:?}", data.uuid, existing_attendees);
+    #[test]
+    fn checkin_window_boundaries() {
+        let window = window();
+        assert_eq!(window.state_at(at("2026-04-01T08:59:59+09:00")), WindowState::NotYetOpen);
+        assert_eq!(window.state_at(at("2026-04-01T09:00:00+09:00")), WindowState::Open);
+        assert_eq!(window.state_at(at("2026-04-01T09:59:59+09:00")), WindowState::Open);
+        assert_eq!(window.state_at(at("2026-04-01T10:00:00+09:00")), WindowState::Closed);
+        assert_eq!(window.state_at(at("2026-04-01T10:00:01+09:00")), WindowState::Closed);
+    }
 
-    // 参加者の情報をクライアントに送信
-    
+    #[test]
+    fn checkin_window_without_times_is_always_open() {
+        let open_ended = CheckinWindow {
+            closes_at: None,
+            ..window()
+        };
+        assert_eq!(open_ended.state_at(at("2026-04-02T09:00:00+09:00")), WindowState::Open);
+        assert_eq!(CheckinWindow::default().state_at(at("2026-04-01T00:00:00+09:00")), WindowState::Open);
+    }
 
-    "Attendees registered successfully".to_string()
+    #[test]
+    fn checkin_window_late_boundary() {
+        let window = window();
+        assert!(!window.is_late(at("2026-04-01T09:09:59+09:00")));
+        assert!(window.is_late(at("2026-04-01T09:10:00+09:00")));
+    }
 
-}
+    fn policy(absent_after_minutes: Option<i64>) -> ArrivalPolicy {
+        ArrivalPolicy {
+            starts_at: "2026-04-01T09:00:00+09:00".to_string(),
+            late_after_minutes: 5,
+            absent_after_minutes,
+        }
+    }
 
-#[tauri::command]
-fn server_check() -> bool {
-    let is_running = IS_SERVER_RUNNING.lock().unwrap();
-    *is_running
-}
+    #[test]
+    fn arrival_policy_grace_period_boundaries() {
+        let policy = policy(Some(30));
+        assert_eq!(policy.classify(at("2026-04-01T08:50:00+09:00")), ArrivalCategory::OnTime);
+        assert_eq!(policy.classify(at("2026-04-01T09:05:00+09:00")), ArrivalCategory::OnTime);
+        assert_eq!(policy.classify(at("2026-04-01T09:05:01+09:00")), ArrivalCategory::Late);
+        assert_eq!(policy.classify(at("2026-04-01T09:30:00+09:00")), ArrivalCategory::Late);
+        assert_eq!(policy.classify(at("2026-04-01T09:30:01+09:00")), ArrivalCategory::Absent);
+    }
 
+    #[test]
+    fn arrival_policy_without_absent_limit() {
+        assert_eq!(policy(None).classify(at("2026-04-01T18:00:00+09:00")), ArrivalCategory::Late);
 
+        let no_grace = ArrivalPolicy {
+            late_after_minutes: 0,
+            ..policy(None)
+        };
+        assert_eq!(no_grace.classify(at("2026-04-01T09:00:00+09:00")), ArrivalCategory::OnTime);
+        assert_eq!(no_grace.classify(at("2026-04-01T09:00:01+09:00")), ArrivalCategory::Late);
+    }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    use tauri::Manager;
-    
-    tauri::Builder::default()
-        .plugin(tauri_plugin_opener::init())
-        .plugin(tauri_plugin_dialog::init())
-        .manage(AppState::default())
-        .invoke_handler(tauri::generate_handler![
-            register_event, debug_hashmap, get_event, get_all_events, debug_run_server, register_attendees, get_local_ip , json_to_attendees, json_to_today, server_check
-        ])
-        .setup(|app| {
-            #[cfg(desktop)]
-            {
-                let handle = app.handle().clone();
-                
-                if let Some(window) = app.get_webview_window("main") {
-                    window.on_window_event(move |event| {
-                        if let tauri::WindowEvent::CloseRequested { api, .. } = event {
-                            let handle_clone = handle.clone();
-                            
-                            // デフォルトの閉じる動作を防ぐ
-                            api.prevent_close();
-                            
-                            // 確認ダイアログを表示
-                            tauri::async_runtime::spawn(async move {
-                                use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
-                                
-                                let answer = handle_clone.dialog()
-                                    .message("アプリケーションを終了しますか？")
-                                    .title("終了確認")
-                                    .kind(MessageDialogKind::Warning)
-                                    .blocking_show();
-                                
-                                if answer {
-                                    // ユーザーが「終了」を選択
-                                    handle_clone.exit(0);
-                                }
-                            });
-                        }
-                    });
-                }
-            }
-            
-            Ok(())
-        })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+    #[test]
+    fn arrival_policy_with_invalid_start_is_on_time() {
+        let invalid = ArrivalPolicy {
+            starts_at: "not a time".to_string(),
+            ..policy(Some(30))
+        };
+        assert_eq!(invalid.classify(at("2026-04-01T18:00:00+09:00")), ArrivalCategory::OnTime);
+    }
 }
-
@@ -0,0 +1,162 @@
+use log::LevelFilter;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "desktop")]
+use tauri::{AppHandle, Manager, Runtime};
+#[cfg(feature = "desktop")]
+use tauri_plugin_log::{RotationStrategy, Target, TargetKind, TimezoneStrategy};
+
+// ログファイル名（拡張子なし）。サイズを超えると server_<日時>.log に切り替わる
+pub const LOG_FILE_NAME: &str = "server";
+#[cfg(feature = "desktop")]
+const MAX_LOG_FILE_SIZE: u128 = 1024 * 1024;
+// 残しておくログファイルの数（現在のファイルを含む）
+#[cfg(feature = "desktop")]
+const MAX_LOG_FILES: usize = 5;
+// ログの出力レベルを変更する環境変数（error, warn, info, debug, trace）
+const LOG_LEVEL_ENV: &str = "LIST_CHECKER_LOG";
+
+// ログの出力レベル（環境変数の指定がなければ info、debug フィーチャー有効時は debug）
+pub fn log_level() -> LevelFilter {
+    std::env::var(LOG_LEVEL_ENV)
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(if cfg!(feature = "debug") { LevelFilter::Debug } else { LevelFilter::Info })
+}
+
+// ログファイルの保存先（アプリのデータディレクトリ内の logs）
+#[cfg(feature = "desktop")]
+pub fn log_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("logs"))
+        .map_err(|e| format!("ログの保存先を取得できませんでした: {}", e))
+}
+
+// 標準出力とログファイルへの出力を開始する
+#[cfg(feature = "desktop")]
+pub fn init_logging<R: Runtime>(app: &AppHandle<R>) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = log_dir(app)?;
+    std::fs::create_dir_all(&dir)?;
+    // 起動中の削除はプラグインのローテーションに任せ、前回までに残ったファイルのみここで削除する
+    prune_log_files(&dir);
+
+    app.plugin(
+        tauri_plugin_log::Builder::new()
+            .clear_targets()
+            .target(Target::new(TargetKind::Stdout))
+            .target(Target::new(TargetKind::Folder {
+                path: dir,
+                file_name: Some(LOG_FILE_NAME.to_string()),
+            }))
+            .level(log_level())
+            // Socket.IOの内部ログは量が多いため警告以上のみ出力する
+            .level_for("engineioxide", LevelFilter::Warn)
+            .level_for("socketioxide", LevelFilter::Warn)
+            .max_file_size(MAX_LOG_FILE_SIZE)
+            .rotation_strategy(RotationStrategy::KeepSome(MAX_LOG_FILES))
+            .timezone_strategy(TimezoneStrategy::UseLocal)
+            .build(),
+    )?;
+    Ok(())
+}
+
+// デスクトップアプリを使わずに起動した場合（ヘッドレスサーバー）の出力先
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    // 出力レベルは log::set_max_level で絞り込み、Socket.IOの内部ログのみ警告以上にする
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let internal = metadata.target().starts_with("engineioxide") || metadata.target().starts_with("socketioxide");
+        !internal || metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "[{}][{}][{}] {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.target(),
+                record.level(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static STDERR_LOGGER: StderrLogger = StderrLogger;
+
+// 標準エラー出力へのログ出力を開始する
+pub fn init_stderr_logging() {
+    if log::set_logger(&STDERR_LOGGER).is_ok() {
+        log::set_max_level(log_level());
+    }
+}
+
+// ログファイルを古い順に返す
+pub fn log_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<(std::time::SystemTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "log")
+                && path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(|stem| stem.starts_with(LOG_FILE_NAME))
+        })
+        .filter_map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+            Some((modified, path))
+        })
+        .collect();
+    files.sort();
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+// 古いログファイルを削除する（ログの出力を開始する前に呼ぶため標準エラー出力に書く）
+#[cfg(feature = "desktop")]
+fn prune_log_files(dir: &Path) {
+    let files = log_files(dir);
+    let excess = files.len().saturating_sub(MAX_LOG_FILES);
+    for path in &files[..excess] {
+        if let Err(e) = std::fs::remove_file(path) {
+            eprintln!("Failed to remove old log file {}: {}", path.display(), e);
+        }
+    }
+}
+
+// 保存されているログを古い順に連結する（不具合報告用）
+pub fn collect_logs(dir: &Path) -> Result<String, String> {
+    let mut output = String::new();
+    for path in log_files(dir) {
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("ログファイルを読み込めませんでした: {}", e))?;
+        output.push_str(&content);
+    }
+    Ok(output)
+}
+
+// info レベルでは参加者IDの末尾2文字以外を伏せる（debug レベルではそのまま出力）
+pub fn redact_id(id: &str) -> String {
+    if log::log_enabled!(log::Level::Debug) {
+        return id.to_string();
+    }
+    let length = id.chars().count();
+    let hidden = if length <= 2 { length } else { length - 2 };
+    let visible: String = id.chars().skip(hidden).collect();
+    format!("{}{}", "*".repeat(hidden), visible)
+}
+
+// 参加者IDの一覧は debug レベルでのみ内容を出力し、info レベルでは件数のみ出力する
+pub fn redact_ids<T: std::fmt::Debug>(ids: &[T]) -> String {
+    if log::log_enabled!(log::Level::Debug) {
+        format!("{:?}", ids)
+    } else {
+        format!("{}件", ids.len())
+    }
+}
@@ -0,0 +1,108 @@
+use serde::Serialize;
+use log::{error, warn};
+use socketioxide::extract::AckSender;
+
+use crate::EVENT_NOT_FOUND;
+
+// ソケットの応答で返すエラーコード（端末はこの値で表示を切り替える）
+//
+// - invalid_request: 送信データの形式が不正、またはイベントIDが空
+// - event_not_found: 指定されたイベントが存在しない
+// - session_not_found: 指定されたセッションが存在しない
+// - outside_window: 受付時間外のため登録できない
+// - ontheday_not_allowed: 当日参加が許可されていない
+// - unknown_id: 名簿にないID
+// - not_present: 在室していない参加者を退室させようとした
+// - forbidden: モニタ以外の端末から管理用のイベントを送信した
+// - device_not_found: 指定された端末が存在しない
+// - device_locked: 端末が一時停止中のため登録できない
+// - banned: 接続元のIPアドレスが禁止されている
+// - rate_limited: 送信回数が上限を超えた
+// - too_large: IDの数や文字列の長さが上限を超えた
+// - ontheday_limit_reached: 当日参加者が上限に達した
+// - unknown_event: 登録されていないイベントを送信した
+// - not_joined: joinしていないイベントに対して送信した
+// - invalid_token: 端末トークンが無効・期限切れ・使用中、またはトークンが必要なイベントに指定せずにjoinした
+// - invalid_code: セルフ出席登録のコードが正しくない、または有効期限が切れている
+// - invalid_qr_token: QRコードを読み取らずに登録した、またはQRコードが無効・期限切れ・使用済み
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidRequest,
+    EventNotFound,
+    SessionNotFound,
+    OutsideWindow,
+    OnthedayNotAllowed,
+    UnknownId,
+    NotPresent,
+    Forbidden,
+    DeviceNotFound,
+    DeviceLocked,
+    Banned,
+    RateLimited,
+    TooLarge,
+    OnthedayLimitReached,
+    UnknownEvent,
+    NotJoined,
+    InvalidToken,
+    InvalidCode,
+    InvalidQrToken,
+}
+
+// すべてのイベントに共通の応答 { ok, error_code, message, data }
+#[derive(Serialize, Debug, Clone)]
+pub struct AckResponse<T: Serialize> {
+    pub ok: bool,
+    pub error_code: Option<ErrorCode>,
+    pub message: Option<String>,
+    pub data: Option<T>,
+}
+
+impl<T: Serialize> AckResponse<T> {
+    pub fn ok(data: T) -> Self {
+        Self {
+            ok: true,
+            error_code: None,
+            message: None,
+            data: Some(data),
+        }
+    }
+
+    pub fn error(error_code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error_code: Some(error_code),
+            message: Some(message.into()),
+            data: None,
+        }
+    }
+
+    // エラーでも途中までの結果を返す場合に使う
+    pub fn with_data(mut self, data: T) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+impl AckResponse<()> {
+    pub fn event_not_found() -> Self {
+        Self::error(ErrorCode::EventNotFound, EVENT_NOT_FOUND)
+    }
+
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::error(ErrorCode::InvalidRequest, message)
+    }
+}
+
+// 応答を返す（応答を求めていないクライアントには何も送信されない）
+pub fn reply<T: Serialize>(ack: AckSender, response: AckResponse<T>) {
+    if let Err(e) = ack.send(&response) {
+        error!("Failed to send acknowledgement: {}", e);
+    }
+}
+
+// 受信データの形式が不正な場合の応答
+pub fn reject_invalid(ack: AckSender, error: impl std::fmt::Display) {
+    warn!("Invalid request data: {}", error);
+    reply(ack, AckResponse::invalid_request(format!("送信データの形式が正しくありません: {}", error)));
+}
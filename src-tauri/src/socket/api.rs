@@ -0,0 +1,338 @@
+use axum::extract::{Path, Query, Request};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::{
+    broadcast_attendance, broadcast_logs, enable_qr_checkin, enable_self_checkin, get_event_store, get_metrics,
+    get_socket_io, import_card_ids, issue_device_token, outcome_error, redact_id, reissue_join_code,
+    revoke_device_token, set_require_device_token, validate_lengths, AckResponse, CardImport, CheckinOutcome,
+    CheckinResult, DeviceRole, DeviceToken, ErrorCode, LogEntry, Settings, DEVICE_TOKEN_NOT_FOUND, EVENT_NOT_FOUND,
+    MAX_ID_LENGTH,
+};
+
+// 管理APIのトークンを指定する環境変数（未指定の場合、管理APIは無効）
+const ADMIN_TOKEN_ENV: &str = "LIST_CHECKER_ADMIN_TOKEN";
+
+static ADMIN_TOKEN: OnceLock<Option<String>> = OnceLock::new();
+
+// 管理APIのトークンを設定する（サーバーの起動前に1回だけ呼ぶ）
+pub fn set_admin_token(token: String) {
+    let _ = ADMIN_TOKEN.set(Some(token).filter(|token| !token.is_empty()));
+}
+
+pub fn admin_token() -> Option<&'static str> {
+    ADMIN_TOKEN
+        .get_or_init(|| std::env::var(ADMIN_TOKEN_ENV).ok().filter(|token| !token.is_empty()))
+        .as_deref()
+}
+
+// 長さ以外の情報から一致した文字数が分からないように比較する
+pub(crate) fn token_matches(expected: &str, actual: &str) -> bool {
+    if expected.len() != actual.len() {
+        return false;
+    }
+    expected.bytes().zip(actual.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+type ApiResult<T> = Result<Json<AckResponse<T>>, ApiError>;
+
+pub struct ApiError(StatusCode, AckResponse<()>);
+
+impl ApiError {
+    fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        let status = match code {
+            ErrorCode::InvalidRequest | ErrorCode::TooLarge => StatusCode::BAD_REQUEST,
+            ErrorCode::Forbidden => StatusCode::UNAUTHORIZED,
+            ErrorCode::EventNotFound | ErrorCode::SessionNotFound | ErrorCode::DeviceNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::CONFLICT,
+        };
+        Self(status, AckResponse::error(code, message))
+    }
+
+    fn event_not_found() -> Self {
+        Self::new(ErrorCode::EventNotFound, EVENT_NOT_FOUND)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(self.1)).into_response()
+    }
+}
+
+// Authorization: Bearer <トークン> を確認する
+async fn require_admin(headers: HeaderMap, request: Request, next: Next) -> Response {
+    let Some(expected) = admin_token() else {
+        return ApiError::new(ErrorCode::Forbidden, "管理APIは無効です").into_response();
+    };
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token_matches(expected, token.trim()));
+    if !authorized {
+        warn!("Rejected admin API request without a valid token: {}", request.uri().path());
+        return ApiError::new(ErrorCode::Forbidden, "管理APIのトークンが正しくありません").into_response();
+    }
+    next.run(request).await
+}
+
+// イベントの概要と現在の出席数
+#[derive(Serialize, Debug, Clone)]
+pub struct EventSummary {
+    pub id: String,
+    pub name: String,
+    pub participants: usize,
+    pub attendees: usize,
+    pub ontheday: usize,
+    pub devices_online: usize,
+    pub active_session: Option<String>,
+    pub join_code: Option<String>,
+    pub require_device_token: bool,
+    pub self_checkin: bool,
+    pub qr_checkin: bool,
+    pub settings: Settings,
+    pub version: u64,
+}
+
+fn event_summary(id: &str) -> Option<EventSummary> {
+    get_event_store().read(id, |state| {
+        let session = state.current_session();
+        EventSummary {
+            id: id.to_string(),
+            name: state.event.eventname.clone(),
+            participants: state.event.participants.len(),
+            attendees: session.attendees.len(),
+            ontheday: session.ontheday.len(),
+            devices_online: state.devices.values().filter(|device| device.online).count(),
+            active_session: state.active_session(),
+            join_code: state.event.join_code.clone(),
+            require_device_token: state.event.require_device_token,
+            self_checkin: state.event.self_checkin,
+            qr_checkin: state.event.qr_checkin,
+            settings: state.settings(),
+            version: state.version,
+        }
+    })
+}
+
+// GET /api/events
+async fn list_events() -> ApiResult<Vec<EventSummary>> {
+    let mut ids = get_event_store().ids();
+    ids.sort();
+    Ok(Json(AckResponse::ok(ids.iter().filter_map(|id| event_summary(id)).collect())))
+}
+
+// GET /api/events/{uuid}
+async fn show_event(Path(uuid): Path<String>) -> ApiResult<EventSummary> {
+    event_summary(&uuid).map(|summary| Json(AckResponse::ok(summary))).ok_or_else(ApiError::event_not_found)
+}
+
+#[derive(Deserialize, Debug)]
+struct RegisterBody {
+    id: String,
+}
+
+// POST /api/events/{uuid}/attendance
+async fn register_attendance(Path(uuid): Path<String>, Json(body): Json<RegisterBody>) -> ApiResult<CheckinResult> {
+    validate_lengths([&body.id], MAX_ID_LENGTH).map_err(|message| ApiError::new(ErrorCode::TooLarge, message))?;
+    info!("Admin API register for {}: {}", uuid, redact_id(&body.id));
+
+    let (id, outcome, logs, sync) = get_event_store()
+        .update(&uuid, |state| {
+            let version = state.version;
+            let id = state.resolve_card(&body.id);
+            let outcome = state.check_in_at(&id, chrono::Local::now().fixed_offset(), false);
+            let mut logs: Vec<LogEntry> = Vec::new();
+            if matches!(outcome, CheckinOutcome::Registered { .. }) {
+                get_metrics().record_checkins(&uuid, 1);
+                logs.push(state.push_log("info", format!("出席登録（管理API）: {} が出席しました", id)));
+            }
+            (id, outcome, logs, state.sync_since(version))
+        })
+        .ok_or_else(ApiError::event_not_found)?;
+
+    broadcast_logs(&uuid, &logs).await;
+    broadcast_attendance(&uuid, &sync).await;
+
+    let error = outcome_error(&outcome);
+    let result = CheckinResult { id, outcome };
+    match error {
+        Some((code, message)) => Err(ApiError::new(code, message)),
+        None => Ok(Json(AckResponse::ok(result))),
+    }
+}
+
+// DELETE /api/events/{uuid}/attendance/{id}
+async fn remove_attendance(Path((uuid, id)): Path<(String, String)>) -> ApiResult<Vec<String>> {
+    info!("Admin API remove for {}: {}", uuid, redact_id(&id));
+
+    let (removed, logs, sync) = get_event_store()
+        .update(&uuid, |state| {
+            let version = state.version;
+            let removed = state.remove_attendance(std::slice::from_ref(&id));
+            let logs: Vec<LogEntry> = removed
+                .iter()
+                .map(|id| state.push_log("info", format!("出席取消（管理API）: {} の出席を取り消しました", id)))
+                .collect();
+            (removed, logs, state.sync_since(version))
+        })
+        .ok_or_else(ApiError::event_not_found)?;
+    if removed.is_empty() {
+        return Err(ApiError::new(ErrorCode::NotPresent, "出席が登録されていません"));
+    }
+
+    broadcast_logs(&uuid, &logs).await;
+    broadcast_attendance(&uuid, &sync).await;
+    Ok(Json(AckResponse::ok(removed)))
+}
+
+// PUT /api/events/{uuid}/settings
+async fn update_settings(Path(uuid): Path<String>, Json(settings): Json<Settings>) -> ApiResult<Settings> {
+    info!("Admin API settings for {}: {:?}", uuid, settings);
+
+    get_event_store()
+        .update(&uuid, |state| state.saved_settings = Some(settings.clone()))
+        .ok_or_else(ApiError::event_not_found)?;
+
+    // 端末には update_settings イベントと同じ形式で通知する
+    if let Some(io) = get_socket_io() {
+        if let Err(e) = io.to(uuid.clone()).emit("update_settings_return", &settings).await {
+            error!("Failed to broadcast settings update to room {}: {}", uuid, e);
+            get_metrics().record_broadcast_failure("update_settings_return");
+        }
+    }
+    Ok(Json(AckResponse::ok(settings)))
+}
+
+// POST /api/events/{uuid}/join_code
+async fn regenerate_join_code(Path(uuid): Path<String>) -> ApiResult<String> {
+    info!("Admin API regenerate join code for {}", uuid);
+    let code = reissue_join_code(&uuid).await.map_err(|_| ApiError::event_not_found())?;
+    Ok(Json(AckResponse::ok(code)))
+}
+
+// GET /api/events/{uuid}/device_tokens
+async fn list_device_tokens(Path(uuid): Path<String>) -> ApiResult<Vec<DeviceToken>> {
+    get_event_store()
+        .read(&uuid, |state| Json(AckResponse::ok(state.device_tokens())))
+        .ok_or_else(ApiError::event_not_found)
+}
+
+#[derive(Deserialize, Debug)]
+struct DeviceTokenBody {
+    device_name: String,
+    #[serde(default = "default_token_role")]
+    role: DeviceRole,
+    valid_hours: Option<i64>,
+}
+
+fn default_token_role() -> DeviceRole {
+    DeviceRole::Kiosk
+}
+
+// POST /api/events/{uuid}/device_tokens
+async fn create_device_token(Path(uuid): Path<String>, Json(body): Json<DeviceTokenBody>) -> ApiResult<DeviceToken> {
+    match issue_device_token(&uuid, &body.device_name, body.role, body.valid_hours).await {
+        Ok(token) => Ok(Json(AckResponse::ok(token))),
+        Err(message) if message == EVENT_NOT_FOUND => Err(ApiError::event_not_found()),
+        Err(message) => Err(ApiError::new(ErrorCode::InvalidRequest, message)),
+    }
+}
+
+// DELETE /api/events/{uuid}/device_tokens/{id}
+async fn delete_device_token(Path((uuid, id)): Path<(String, String)>) -> ApiResult<DeviceToken> {
+    match revoke_device_token(&uuid, &id).await {
+        Ok(token) => Ok(Json(AckResponse::ok(token))),
+        Err(message) if message == DEVICE_TOKEN_NOT_FOUND => Err(ApiError::new(ErrorCode::DeviceNotFound, message)),
+        Err(_) => Err(ApiError::event_not_found()),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RequireDeviceTokenBody {
+    required: bool,
+}
+
+// PUT /api/events/{uuid}/require_device_token
+async fn require_device_token(
+    Path(uuid): Path<String>,
+    Json(body): Json<RequireDeviceTokenBody>,
+) -> ApiResult<bool> {
+    set_require_device_token(&uuid, body.required).map_err(|_| ApiError::event_not_found())?;
+    Ok(Json(AckResponse::ok(body.required)))
+}
+
+#[derive(Deserialize, Debug)]
+struct EnabledBody {
+    enabled: bool,
+}
+
+// PUT /api/events/{uuid}/self_checkin
+async fn update_self_checkin(Path(uuid): Path<String>, Json(body): Json<EnabledBody>) -> ApiResult<bool> {
+    enable_self_checkin(&uuid, body.enabled).map_err(|_| ApiError::event_not_found())?;
+    Ok(Json(AckResponse::ok(body.enabled)))
+}
+
+// PUT /api/events/{uuid}/qr_checkin
+async fn update_qr_checkin(Path(uuid): Path<String>, Json(body): Json<EnabledBody>) -> ApiResult<bool> {
+    enable_qr_checkin(&uuid, body.enabled).map_err(|_| ApiError::event_not_found())?;
+    Ok(Json(AckResponse::ok(body.enabled)))
+}
+
+#[derive(Deserialize, Debug)]
+struct CardIdsBody {
+    csv: String,
+    #[serde(default)]
+    replace: bool,
+}
+
+// POST /api/events/{uuid}/card_ids
+async fn upload_card_ids(Path(uuid): Path<String>, Json(body): Json<CardIdsBody>) -> ApiResult<CardImport> {
+    let result = import_card_ids(&uuid, &body.csv, body.replace)
+        .await
+        .map_err(|_| ApiError::event_not_found())?;
+    Ok(Json(AckResponse::ok(result)))
+}
+
+#[derive(Deserialize, Debug)]
+struct ExportQuery {
+    session: Option<String>,
+}
+
+// GET /api/events/{uuid}/export?session=<セッションID>
+async fn export_attendance(Path(uuid): Path<String>, Query(query): Query<ExportQuery>) -> Result<Response, ApiError> {
+    let csv = get_event_store()
+        .read(&uuid, |state| {
+            let session = query.session.clone().or_else(|| state.active_session());
+            state.export_csv(session.as_deref())
+        })
+        .ok_or_else(ApiError::event_not_found)?;
+    Ok(([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], csv).into_response())
+}
+
+// 管理API（トークンが設定されている場合のみ有効）
+pub fn admin_api_router() -> Router {
+    Router::new()
+        .route("/api/events", get(list_events))
+        .route("/api/events/{uuid}", get(show_event))
+        .route("/api/events/{uuid}/attendance", post(register_attendance))
+        .route("/api/events/{uuid}/attendance/{id}", delete(remove_attendance))
+        .route("/api/events/{uuid}/settings", put(update_settings))
+        .route("/api/events/{uuid}/export", get(export_attendance))
+        .route("/api/events/{uuid}/join_code", post(regenerate_join_code))
+        .route("/api/events/{uuid}/device_tokens", get(list_device_tokens).post(create_device_token))
+        .route("/api/events/{uuid}/device_tokens/{id}", delete(delete_device_token))
+        .route("/api/events/{uuid}/require_device_token", put(require_device_token))
+        .route("/api/events/{uuid}/self_checkin", put(update_self_checkin))
+        .route("/api/events/{uuid}/qr_checkin", put(update_qr_checkin))
+        .route("/api/events/{uuid}/card_ids", post(upload_card_ids))
+        .layer(axum::middleware::from_fn(require_admin))
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// 登録・設定変更など、状態を変更するイベントの送信回数の上限（1分あたり）
+pub const SOCKET_EVENTS_PER_MINUTE: u32 = 120;
+pub const IP_EVENTS_PER_MINUTE: u32 = 300;
+
+// 1回の送信に含められるIDの数と、文字列の長さの上限
+pub const MAX_IDS_PER_REQUEST: usize = 500;
+pub const MAX_ID_LENGTH: usize = 64;
+pub const MAX_NAME_LENGTH: usize = 64;
+pub const MAX_MESSAGE_LENGTH: usize = 500;
+
+// 保持する集計の数がこれを超えたら、期限切れのものを削除する
+const PRUNE_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimit {
+    Allowed,
+    // first: この期間で初めて上限を超えた（ログはこのときだけ保存する）
+    Exceeded { first: bool },
+}
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+// キー（ソケットIDやIPアドレス）ごとに一定期間の送信回数を数える
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: u32,
+    period: Duration,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, period: Duration) -> Self {
+        Self {
+            limit,
+            period,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, key: &str) -> RateLimit {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+
+        if windows.len() > PRUNE_THRESHOLD {
+            windows.retain(|_, window| now.duration_since(window.started) < self.period);
+        }
+
+        let window = windows.entry(key.to_string()).or_insert(Window { started: now, count: 0 });
+        if now.duration_since(window.started) >= self.period {
+            window.started = now;
+            window.count = 0;
+        }
+
+        window.count += 1;
+        if window.count <= self.limit {
+            RateLimit::Allowed
+        } else {
+            RateLimit::Exceeded {
+                first: window.count == self.limit + 1,
+            }
+        }
+    }
+}
+
+static SOCKET_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+static IP_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+pub fn get_socket_limiter() -> &'static RateLimiter {
+    SOCKET_LIMITER.get_or_init(|| RateLimiter::new(SOCKET_EVENTS_PER_MINUTE, Duration::from_secs(60)))
+}
+
+pub fn get_ip_limiter() -> &'static RateLimiter {
+    IP_LIMITER.get_or_init(|| RateLimiter::new(IP_EVENTS_PER_MINUTE, Duration::from_secs(60)))
+}
+
+// IDの数と長さを確認する
+pub fn validate_ids<'a>(ids: impl ExactSizeIterator<Item = &'a String>, max_count: usize) -> Result<(), String> {
+    if ids.len() > max_count {
+        return Err(format!("一度に送信できるIDは{}件までです", max_count));
+    }
+    validate_lengths(ids, MAX_ID_LENGTH)
+}
+
+// 名簿のインデックスなど、文字列以外のリストの件数を確認する
+pub fn validate_list_length(length: usize) -> Result<(), String> {
+    if length > MAX_IDS_PER_REQUEST {
+        return Err(format!("一度に送信できるIDは{}件までです", MAX_IDS_PER_REQUEST));
+    }
+    Ok(())
+}
+
+// 文字列の長さ（文字数）を確認する
+pub fn validate_lengths<'a>(values: impl IntoIterator<Item = &'a String>, max_length: usize) -> Result<(), String> {
+    match values.into_iter().find(|value| value.chars().count() > max_length) {
+        Some(value) => Err(format!(
+            "{}文字を超える値は送信できません（{}...）",
+            max_length,
+            value.chars().take(16).collect::<String>()
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(limiter: &RateLimiter, key: &str, times: u32) -> Vec<RateLimit> {
+        (0..times).map(|_| limiter.check(key)).collect()
+    }
+
+    #[test]
+    fn socket_window_allows_up_to_the_limit() {
+        let limiter = RateLimiter::new(SOCKET_EVENTS_PER_MINUTE, Duration::from_secs(60));
+        assert!(send(&limiter, "socket-a", SOCKET_EVENTS_PER_MINUTE).iter().all(|result| *result == RateLimit::Allowed));
+        assert_eq!(limiter.check("socket-a"), RateLimit::Exceeded { first: true });
+        assert_eq!(limiter.check("socket-a"), RateLimit::Exceeded { first: false });
+        // 他の端末の送信回数には影響しない
+        assert_eq!(limiter.check("socket-b"), RateLimit::Allowed);
+    }
+
+    #[test]
+    fn ip_window_counts_every_socket_from_the_address() {
+        let limiter = RateLimiter::new(IP_EVENTS_PER_MINUTE, Duration::from_secs(60));
+        send(&limiter, "192.168.0.10", IP_EVENTS_PER_MINUTE);
+        assert_eq!(limiter.check("192.168.0.10"), RateLimit::Exceeded { first: true });
+        assert_eq!(limiter.check("192.168.0.11"), RateLimit::Allowed);
+    }
+
+    #[test]
+    fn window_resets_after_the_period() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(50));
+        assert_eq!(limiter.check("socket"), RateLimit::Allowed);
+        assert_eq!(limiter.check("socket"), RateLimit::Exceeded { first: true });
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(limiter.check("socket"), RateLimit::Allowed);
+    }
+
+    #[test]
+    fn validates_id_count_and_length() {
+        let ids: Vec<String> = (0..=MAX_IDS_PER_REQUEST).map(|i| i.to_string()).collect();
+        assert!(validate_ids(ids[..MAX_IDS_PER_REQUEST].iter(), MAX_IDS_PER_REQUEST).is_ok());
+        assert!(validate_ids(ids.iter(), MAX_IDS_PER_REQUEST).is_err());
+        assert!(validate_list_length(MAX_IDS_PER_REQUEST).is_ok());
+        assert!(validate_list_length(MAX_IDS_PER_REQUEST + 1).is_err());
+
+        let long = ["あ".repeat(MAX_ID_LENGTH), "あ".repeat(MAX_ID_LENGTH + 1)];
+        assert!(validate_lengths(&long[..1], MAX_ID_LENGTH).is_ok());
+        assert!(validate_lengths(&long, MAX_ID_LENGTH).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::{get_event_store, DeviceRole};
+
+// /metrics を公開するかどうかを指定する環境変数（1 または true で有効）
+const METRICS_ENV: &str = "LIST_CHECKER_METRICS";
+
+// 処理時間のヒストグラムの区切り（秒）
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+// 1分あたりの登録数を数える期間
+const CHECKIN_WINDOW: Duration = Duration::from_secs(60);
+
+pub fn metrics_enabled() -> bool {
+    std::env::var(METRICS_ENV).is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct MetricsData {
+    checkins_total: HashMap<String, u64>,
+    recent_checkins: HashMap<String, VecDeque<(Instant, usize)>>,
+    handler_latency: HashMap<&'static str, Histogram>,
+    broadcast_failures: HashMap<String, u64>,
+}
+
+// サーバーの負荷を監視するための集計（Prometheus形式で出力する）
+#[derive(Debug, Default)]
+pub struct Metrics {
+    data: Mutex<MetricsData>,
+}
+
+impl Metrics {
+    pub fn record_checkins(&self, room: &str, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let mut data = self.data.lock().unwrap();
+        *data.checkins_total.entry(room.to_string()).or_default() += count as u64;
+        data.recent_checkins.entry(room.to_string()).or_default().push_back((Instant::now(), count));
+    }
+
+    pub fn record_latency(&self, event: &'static str, elapsed: Duration) {
+        let mut data = self.data.lock().unwrap();
+        data.handler_latency.entry(event).or_default().observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_broadcast_failure(&self, event: &str) {
+        let mut data = self.data.lock().unwrap();
+        *data.broadcast_failures.entry(event.to_string()).or_default() += 1;
+    }
+
+    // Prometheusのテキスト形式で出力する
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        self.render_counters(&mut output);
+        render_state(&mut output);
+        output
+    }
+
+    fn render_counters(&self, output: &mut String) {
+        let now = Instant::now();
+        let mut data = self.data.lock().unwrap();
+
+        header(output, "list_checker_checkins_total", "counter", "Total number of check-ins per room");
+        for (room, total) in sorted(&data.checkins_total) {
+            let _ = writeln!(output, "list_checker_checkins_total{{room=\"{}\"}} {}", room_label(room), total);
+        }
+
+        header(output, "list_checker_checkins_per_minute", "gauge", "Check-ins during the last minute per room");
+        for recent in data.recent_checkins.values_mut() {
+            while recent.front().is_some_and(|(at, _)| now.duration_since(*at) > CHECKIN_WINDOW) {
+                recent.pop_front();
+            }
+        }
+        let mut rooms: Vec<_> = data.recent_checkins.iter().collect();
+        rooms.sort_by(|a, b| a.0.cmp(b.0));
+        for (room, recent) in rooms {
+            let count: usize = recent.iter().map(|(_, count)| count).sum();
+            let _ = writeln!(output, "list_checker_checkins_per_minute{{room=\"{}\"}} {}", room_label(room), count);
+        }
+
+        header(output, "list_checker_handler_duration_seconds", "histogram", "Socket.IO handler latency");
+        let mut events: Vec<_> = data.handler_latency.iter().collect();
+        events.sort_by(|a, b| a.0.cmp(b.0));
+        for (event, histogram) in events {
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    output,
+                    "list_checker_handler_duration_seconds_bucket{{event=\"{}\",le=\"{}\"}} {}",
+                    event, bound, count
+                );
+            }
+            let _ = writeln!(
+                output,
+                "list_checker_handler_duration_seconds_bucket{{event=\"{}\",le=\"+Inf\"}} {}",
+                event, histogram.count
+            );
+            let _ = writeln!(output, "list_checker_handler_duration_seconds_sum{{event=\"{}\"}} {}", event, histogram.sum);
+            let _ = writeln!(output, "list_checker_handler_duration_seconds_count{{event=\"{}\"}} {}", event, histogram.count);
+        }
+
+        header(output, "list_checker_broadcast_failures_total", "counter", "Failed room broadcasts per event");
+        for (event, total) in sorted(&data.broadcast_failures) {
+            let _ = writeln!(output, "list_checker_broadcast_failures_total{{event=\"{}\"}} {}", escape(event), total);
+        }
+    }
+}
+
+// イベントごとの接続端末数と保持しているデータの件数
+fn render_state(output: &mut String) {
+    let store = get_event_store();
+    let mut rooms: Vec<_> = store
+        .ids()
+        .into_iter()
+        .filter_map(|uuid| {
+            store
+                .read(&uuid, |state| {
+                    let session = state.current_session();
+                    let devices = state.devices.values().filter(|device| device.online);
+                    let mut kiosks = 0;
+                    let mut monitors = 0;
+                    let mut unknown = 0;
+                    for device in devices {
+                        match device.role {
+                            DeviceRole::Kiosk => kiosks += 1,
+                            DeviceRole::Monitor => monitors += 1,
+                            DeviceRole::Unknown => unknown += 1,
+                        }
+                    }
+                    let entries = [
+                        ("attendees", session.attendees.len()),
+                        ("ontheday", session.ontheday.len()),
+                        ("logs", state.logs.len()),
+                        ("changes", state.changes.len()),
+                        ("devices", state.devices.len()),
+                        ("processed_ops", state.processed_ops.len()),
+                    ];
+                    ([("kiosk", kiosks), ("monitor", monitors), ("unknown", unknown)], entries, state.version)
+                })
+                .map(|values| (uuid, values))
+        })
+        .collect();
+    rooms.sort_by(|a, b| a.0.cmp(&b.0));
+
+    header(output, "list_checker_connected_devices", "gauge", "Connected devices per room and role");
+    for (room, (devices, _, _)) in &rooms {
+        for (role, count) in devices {
+            let _ = writeln!(
+                output,
+                "list_checker_connected_devices{{room=\"{}\",role=\"{}\"}} {}",
+                room_label(room),
+                role,
+                count
+            );
+        }
+    }
+
+    header(output, "list_checker_state_entries", "gauge", "Number of entries held in memory per room");
+    for (room, (_, entries, _)) in &rooms {
+        for (kind, count) in entries {
+            let _ = writeln!(output, "list_checker_state_entries{{room=\"{}\",kind=\"{}\"}} {}", room_label(room), kind, count);
+        }
+    }
+
+    header(output, "list_checker_attendance_version", "gauge", "Current attendance version per room");
+    for (room, (_, _, version)) in &rooms {
+        let _ = writeln!(output, "list_checker_attendance_version{{room=\"{}\"}} {}", room_label(room), version);
+    }
+}
+
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+fn sorted(values: &HashMap<String, u64>) -> Vec<(&String, &u64)> {
+    let mut values: Vec<_> = values.iter().collect();
+    values.sort();
+    values
+}
+
+// room のラベル（/metrics は認証なしで公開するため、参加に使えるイベントIDの代わりにハッシュの先頭を出力する）
+fn room_label(room: &str) -> String {
+    Sha256::digest(room.as_bytes())[..6].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// ラベルの値に含まれる記号をエスケープする
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// ハンドラーの処理時間を計測する（破棄されたときに記録する）
+pub struct HandlerTimer {
+    event: &'static str,
+    started: Instant,
+}
+
+impl HandlerTimer {
+    pub fn start(event: &'static str) -> Self {
+        Self {
+            event,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for HandlerTimer {
+    fn drop(&mut self) {
+        get_metrics().record_latency(self.event, self.started.elapsed());
+    }
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn get_metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::default)
+}
+
+// GET /metrics
+pub async fn metrics_handler() -> ([(axum::http::header::HeaderName, &'static str); 1], String) {
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        get_metrics().render(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_event, Eventstruct};
+
+    #[test]
+    fn render_labels_rooms_without_event_ids() {
+        let event: Eventstruct = serde_json::from_value(serde_json::json!({
+            "eventname": "metrics",
+            "eventinfo": "",
+            "participants": ["a"],
+            "arrowtoday": false,
+            "autotodayregister": false,
+            "nolist": false,
+            "soukai": false,
+        }))
+        .unwrap();
+        let uuid = create_event(event);
+        let metrics = Metrics::default();
+        metrics.record_checkins(&uuid, 2);
+        metrics.record_broadcast_failure("devices_update");
+
+        let output = metrics.render();
+        let label = format!("room=\"{}\"", room_label(&uuid));
+        assert!(!output.contains(&uuid));
+        assert!(output.contains(&format!("list_checker_checkins_total{{{}}} 2", label)));
+        assert!(output.contains(&format!("list_checker_checkins_per_minute{{{}}} 2", label)));
+        assert!(output.contains(&format!("list_checker_attendance_version{{{}}} 0", label)));
+        assert!(output.contains("list_checker_broadcast_failures_total{event=\"devices_update\"} 1"));
+        assert!(output.contains("# TYPE list_checker_handler_duration_seconds histogram"));
+    }
+}
@@ -1,3 +1,15 @@
+pub mod ack;
+pub mod api;
+pub mod limit;
+pub mod metrics;
+pub mod qr_checkin;
+pub mod self_checkin;
 pub mod server;
 
+pub use ack::*;
+pub use api::*;
+pub use limit::*;
+pub use metrics::*;
+pub use qr_checkin::*;
+pub use self_checkin::*;
 pub use server::*;
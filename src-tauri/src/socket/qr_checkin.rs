@@ -0,0 +1,208 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+use crate::{emit_to_monitors, get_event_store, trusted_monitor_sockets, unix_time, EventState};
+
+// モニタのQRコードを切り替える間隔（秒）
+pub const QR_TOKEN_ROTATE_SECS: u64 = 5;
+// QRコードのトークンの有効期限（秒）。読み取ってから送信するまでの時間を見込む
+pub const QR_TOKEN_TTL_SECS: u64 = 15;
+// 署名の長さ（バイト）
+const QR_TOKEN_SIGNATURE_BYTES: usize = 16;
+
+pub const QR_TOKEN_REQUIRED: &str = "モニタに表示されているQRコードを読み取ってから登録してください";
+const QR_TOKEN_INVALID: &str = "QRコードが正しくありません。もう一度読み取ってください";
+const QR_TOKEN_EXPIRED: &str = "QRコードの有効期限が切れています。もう一度読み取ってください";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// モニタに表示するトークンと、次に切り替えるまでの秒数
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct QrCheckinToken {
+    pub token: String,
+    pub refresh_in: u64,
+}
+
+fn sign(secret: &str, payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// 「発行時刻.nonce.署名」の形式で、イベントごとの秘密鍵で署名したトークンを発行する
+pub fn issue_qr_token(secret: &str, at: SystemTime) -> String {
+    let nonce = &Uuid::new_v4().simple().to_string()[..8];
+    let payload = format!("{}.{}", unix_time(at), nonce);
+    let signature = sign(secret, &payload).finalize().into_bytes();
+    let signature: String = signature[..QR_TOKEN_SIGNATURE_BYTES]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}.{}", payload, signature)
+}
+
+// 署名と有効期限を確認し、発行時刻とnonceを返す
+pub fn verify_qr_token(secret: &str, token: &str, at: SystemTime) -> Result<(u64, String), &'static str> {
+    let mut parts = token.trim().split('.');
+    let (Some(issued_at), Some(nonce), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(QR_TOKEN_INVALID);
+    };
+    let signature = decode_hex(signature)
+        .filter(|signature| signature.len() == QR_TOKEN_SIGNATURE_BYTES)
+        .ok_or(QR_TOKEN_INVALID)?;
+    sign(secret, &format!("{}.{}", issued_at, nonce))
+        .verify_truncated_left(&signature)
+        .map_err(|_| QR_TOKEN_INVALID)?;
+
+    let issued_at: u64 = issued_at.parse().map_err(|_| QR_TOKEN_INVALID)?;
+    let now = unix_time(at);
+    // 発行時刻はサーバーの時刻のため、未来の時刻は時計が戻された場合のみ
+    if issued_at > now || now - issued_at > QR_TOKEN_TTL_SECS {
+        return Err(QR_TOKEN_EXPIRED);
+    }
+    Ok((issued_at, nonce.to_string()))
+}
+
+pub fn current_qr_token(state: &EventState) -> Option<QrCheckinToken> {
+    state.event.qr_checkin.then(|| QrCheckinToken {
+        token: issue_qr_token(&state.qr_secret, SystemTime::now()),
+        refresh_in: QR_TOKEN_ROTATE_SECS,
+    })
+}
+
+// QRコードによる出席登録が有効なイベントで、主催者が用意した端末以外からの登録か
+pub fn requires_qr_token(state: &EventState, socket_id: &str) -> bool {
+    state.event.qr_checkin && !state.devices.get(socket_id).is_some_and(|device| state.is_trusted_device(device))
+}
+
+// QRコードのトークンを確認する（1回の登録で出席できるのは1人のみで、トークンの使用は送信元ごとに記録する）
+pub fn check_qr_checkin(
+    state: &mut EventState,
+    socket_id: &str,
+    client: &str,
+    token: Option<&str>,
+    new_ids: &[String],
+) -> Result<(), String> {
+    if !requires_qr_token(state, socket_id) {
+        return Ok(());
+    }
+    let Some(token) = token else {
+        return Err(QR_TOKEN_REQUIRED.to_string());
+    };
+    let now = SystemTime::now();
+    let (issued_at, nonce) = verify_qr_token(&state.qr_secret, token, now)?;
+    match new_ids {
+        [] => Ok(()),
+        [id] => state.use_qr_token(&nonce, issued_at, client, id, unix_time(now)),
+        _ => Err("QRコードで出席登録できるのは1人ずつです".to_string()),
+    }
+}
+
+// QRコードによる出席登録が有効なイベントのモニタに、新しいトークンを送信する
+pub(crate) async fn watch_qr_tokens() {
+    let mut interval = tokio::time::interval(Duration::from_secs(QR_TOKEN_ROTATE_SECS));
+    loop {
+        interval.tick().await;
+
+        let store = get_event_store();
+        for uuid in store.ids() {
+            let Some(Some((token, monitors))) =
+                store.read(&uuid, |state| current_qr_token(state).map(|token| (token, trusted_monitor_sockets(state))))
+            else {
+                continue;
+            };
+            emit_to_monitors(&monitors, "qr_token", &token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Eventstruct;
+    use std::time::UNIX_EPOCH;
+
+    const SECRET: &str = "event-secret";
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn accepts_valid_token() {
+        let issued = at(1_700_000_000);
+        let token = issue_qr_token(SECRET, issued);
+        let (issued_at, nonce) = verify_qr_token(SECRET, &token, at(1_700_000_000 + QR_TOKEN_TTL_SECS)).unwrap();
+        assert_eq!(issued_at, 1_700_000_000);
+        assert_eq!(token.split('.').nth(1), Some(nonce.as_str()));
+    }
+
+    #[test]
+    fn rejects_expired_token() {
+        let token = issue_qr_token(SECRET, at(1_700_000_000));
+        assert_eq!(
+            verify_qr_token(SECRET, &token, at(1_700_000_000 + QR_TOKEN_TTL_SECS + 1)),
+            Err(QR_TOKEN_EXPIRED)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_token() {
+        let now = at(1_700_000_000);
+        let token = issue_qr_token(SECRET, now);
+        let parts: Vec<&str> = token.split('.').collect();
+
+        let nonce = format!("{}.{}.{}", parts[0], "00000000", parts[2]);
+        assert_eq!(verify_qr_token(SECRET, &nonce, now), Err(QR_TOKEN_INVALID));
+        let flipped = if parts[2].starts_with('0') { "1" } else { "0" };
+        let signature = format!("{}.{}.{}{}", parts[0], parts[1], flipped, &parts[2][1..]);
+        assert_eq!(verify_qr_token(SECRET, &signature, now), Err(QR_TOKEN_INVALID));
+        assert_eq!(verify_qr_token("another-secret", &token, now), Err(QR_TOKEN_INVALID));
+    }
+
+    #[test]
+    fn rejects_malformed_token() {
+        let now = at(1_700_000_000);
+        let token = issue_qr_token(SECRET, now);
+        for malformed in ["", "1700000000", "1700000000.nonce", "1700000000.nonce.zz", &format!("{}.extra", token)] {
+            assert_eq!(verify_qr_token(SECRET, malformed, now), Err(QR_TOKEN_INVALID));
+        }
+    }
+
+    #[test]
+    fn rejects_replayed_token() {
+        let event: Eventstruct = serde_json::from_value(serde_json::json!({
+            "eventname": "test",
+            "eventinfo": "",
+            "participants": ["a", "b"],
+            "arrowtoday": false,
+            "autotodayregister": false,
+            "nolist": false,
+            "soukai": false,
+        }))
+        .unwrap();
+        let mut state = EventState::new(event);
+        let now = 1_700_000_000;
+        let token = issue_qr_token(SECRET, at(now));
+        let (issued_at, nonce) = verify_qr_token(SECRET, &token, at(now)).unwrap();
+
+        assert!(state.use_qr_token(&nonce, issued_at, "192.168.0.10", "a", now).is_ok());
+        assert!(state.use_qr_token(&nonce, issued_at, "192.168.0.10", "a", now + 1).is_err());
+        // 同じQRコードでも別の端末からの登録は受け付ける
+        assert!(state.use_qr_token(&nonce, issued_at, "192.168.0.11", "b", now + 1).is_ok());
+    }
+}
@@ -0,0 +1,194 @@
+use hmac::{Hmac, Mac};
+use log::error;
+use serde::Serialize;
+use sha2::Sha256;
+use socketioxide::socket::Sid;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{get_event_store, get_socket_io, token_matches, DeviceInfo, DeviceRole, EventState};
+
+// セルフ出席登録のコードを切り替える間隔（秒）
+pub const SELF_CHECKIN_STEP_SECS: u64 = 30;
+const SELF_CHECKIN_CODE_DIGITS: u32 = 6;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// モニタに表示するコードと、次に切り替わるまでの秒数
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SelfCheckinCode {
+    pub code: String,
+    pub expires_in: u64,
+}
+
+pub(crate) fn unix_time(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or_default()
+}
+
+// TOTP（RFC 6238）と同じ方法で、イベントごとの秘密鍵と時刻の区切りからコードを求める
+fn code_for_step(secret: &str, step: u64) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    format!("{:0width$}", value % 10u32.pow(SELF_CHECKIN_CODE_DIGITS), width = SELF_CHECKIN_CODE_DIGITS as usize)
+}
+
+pub fn self_checkin_code(secret: &str, at: SystemTime) -> SelfCheckinCode {
+    let now = unix_time(at);
+    SelfCheckinCode {
+        code: code_for_step(secret, now / SELF_CHECKIN_STEP_SECS),
+        expires_in: SELF_CHECKIN_STEP_SECS - now % SELF_CHECKIN_STEP_SECS,
+    }
+}
+
+// 表示が切り替わった直後に入力した場合も受け付けるため、1つ前のコードまで有効とする
+pub fn verify_self_checkin_code(secret: &str, code: &str, at: SystemTime) -> bool {
+    let step = unix_time(at) / SELF_CHECKIN_STEP_SECS;
+    let code = code.trim();
+    [step, step.saturating_sub(1)].into_iter().any(|step| token_matches(&code_for_step(secret, step), code))
+}
+
+// コードを表示してよいモニタ（サーバーを起動したPC、または有効期限内の端末トークンで参加したモニタ）
+// イベントIDを知っている学生がモニタとして参加してもコードは届かない
+pub fn receives_self_checkin_code(state: &EventState, device: &DeviceInfo) -> bool {
+    device.online && device.role == DeviceRole::Monitor && state.is_trusted_device(device)
+}
+
+// コードを送信するモニタのソケットID
+pub(crate) fn trusted_monitor_sockets(state: &EventState) -> Vec<String> {
+    state
+        .devices
+        .values()
+        .filter(|device| receives_self_checkin_code(state, device))
+        .map(|device| device.socket_id.clone())
+        .collect()
+}
+
+// ソケットIDで指定したモニタに送信する
+pub(crate) fn emit_to_monitors<T: Serialize>(monitors: &[String], event: &'static str, data: &T) {
+    let Some(io) = get_socket_io() else {
+        return;
+    };
+    for socket_id in monitors {
+        let Some(socket) = socket_id.parse::<Sid>().ok().and_then(|sid| io.get_socket(sid)) else {
+            continue;
+        };
+        if let Err(e) = socket.emit(event, data) {
+            error!("Failed to send {}: {}", event, e);
+        }
+    }
+}
+
+pub fn current_self_checkin_code(state: &EventState) -> Option<SelfCheckinCode> {
+    state
+        .event
+        .self_checkin
+        .then(|| self_checkin_code(&state.self_checkin_secret, SystemTime::now()))
+}
+
+// セルフ出席登録が有効なイベントのモニタに、コードが切り替わるたびに送信する
+pub(crate) async fn watch_self_checkin_codes() {
+    loop {
+        let now = unix_time(SystemTime::now());
+        let wait = SELF_CHECKIN_STEP_SECS - now % SELF_CHECKIN_STEP_SECS;
+        tokio::time::sleep(Duration::from_secs(wait)).await;
+
+        let store = get_event_store();
+        for uuid in store.ids() {
+            let Some(Some((code, monitors))) = store.read(&uuid, |state| {
+                current_self_checkin_code(state).map(|code| (code, trusted_monitor_sockets(state)))
+            }) else {
+                continue;
+            };
+            emit_to_monitors(&monitors, "self_checkin_code", &code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Eventstruct;
+
+    const SECRET: &str = "event-secret";
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn accepts_current_step() {
+        let now = at(1_700_000_015);
+        let code = self_checkin_code(SECRET, now);
+        assert_eq!(code.expires_in, 25);
+        assert!(verify_self_checkin_code(SECRET, &code.code, now));
+        assert!(verify_self_checkin_code(SECRET, &format!(" {} ", code.code), now));
+    }
+
+    #[test]
+    fn accepts_previous_step() {
+        let code = self_checkin_code(SECRET, at(1_700_000_015));
+        assert!(verify_self_checkin_code(SECRET, &code.code, at(1_700_000_015 + SELF_CHECKIN_STEP_SECS)));
+    }
+
+    #[test]
+    fn rejects_stale_code() {
+        let code = self_checkin_code(SECRET, at(1_700_000_015));
+        assert!(!verify_self_checkin_code(SECRET, &code.code, at(1_700_000_015 + 2 * SELF_CHECKIN_STEP_SECS)));
+    }
+
+    fn monitor(ip: &str, token_id: Option<&str>) -> DeviceInfo {
+        DeviceInfo {
+            socket_id: "socket".to_string(),
+            role: DeviceRole::Monitor,
+            device_name: String::new(),
+            ip: Some(ip.to_string()),
+            connected_since: String::new(),
+            last_activity: String::new(),
+            checkins: 0,
+            online: true,
+            locked: false,
+            token_id: token_id.map(str::to_string),
+        }
+    }
+
+    fn event_state() -> EventState {
+        let event: Eventstruct = serde_json::from_value(serde_json::json!({
+            "eventname": "test",
+            "eventinfo": "",
+            "participants": [],
+            "arrowtoday": true,
+            "autotodayregister": false,
+            "nolist": false,
+            "soukai": false,
+        }))
+        .unwrap();
+        EventState::new(event)
+    }
+
+    #[test]
+    fn sends_code_only_to_trusted_monitors() {
+        let mut state = event_state();
+        let token = state.issue_device_token("monitor", DeviceRole::Monitor, chrono::Duration::hours(1));
+        assert!(receives_self_checkin_code(&state, &monitor("127.0.0.1", None)));
+        assert!(receives_self_checkin_code(&state, &monitor("192.168.0.20", Some(&token.id))));
+        assert!(!receives_self_checkin_code(&state, &monitor("192.168.0.20", None)));
+        assert!(!receives_self_checkin_code(&state, &monitor("192.168.0.20", Some("unknown"))));
+    }
+
+    #[test]
+    fn expired_token_no_longer_receives_code() {
+        let mut state = event_state();
+        let token = state.issue_device_token("monitor", DeviceRole::Monitor, chrono::Duration::seconds(-1));
+        assert!(!receives_self_checkin_code(&state, &monitor("192.168.0.20", Some(&token.id))));
+        assert!(!state.is_trusted_device(&monitor("192.168.0.20", Some(&token.id))));
+    }
+
+    #[test]
+    fn rejects_code_from_another_event() {
+        let now = at(1_700_000_015);
+        let code = self_checkin_code("another-secret", now);
+        assert!(!verify_self_checkin_code(SECRET, &code.code, now));
+    }
+}
@@ -1,293 +1,1705 @@
-use socketioxide::{extract::{Data, SocketRef}, SocketIo};
+use socketioxide::{extract::{AckSender, Event, SocketRef, TryData}, socket::Sid, SocketIo};
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 use tower_http::services::ServeDir;
-use crate::get_app_state;
-use crate::get_app_state2;
-use crate::get_app_state3;
-use crate::get_app_state4;
+use crate::{
+    admin_api_router, admin_token, check_qr_checkin, current_qr_token, current_self_checkin_code, emit_to_monitors,
+    get_event_store, get_ip_limiter, get_metrics, get_socket_limiter, issue_join_code, metrics_enabled,
+    metrics_handler, receives_self_checkin_code, redact_id, redact_ids, reject_invalid, reply, requires_qr_token,
+    trusted_monitor_sockets, validate_ids, validate_lengths, validate_list_length, verify_self_checkin_code,
+    watch_qr_tokens, watch_self_checkin_codes, AckResponse, AttendanceSync, CardImport, CheckinOp, CheckinOutcome,
+    CheckinResult, DeviceInfo, DeviceRole, DeviceToken, ErrorCode, EventState, HandlerTimer, LogEntry, OpAck,
+    RateLimit, WindowState, CANNOT_BAN_LOOPBACK, DEVICE_NOT_FOUND, EVENT_NOT_FOUND, MAX_IDS_PER_REQUEST, MAX_ID_LENGTH,
+    MAX_MESSAGE_LENGTH, MAX_NAME_LENGTH, QR_TOKEN_REQUIRED,
+};
 use local_ip_address::local_ip;
+use log::{debug, error, info, warn};
 use serde::{ Deserialize, Serialize};
 use crate::IS_SERVER_RUNNING;
-use chrono::Local;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+// Tauriコマンドからもroomへ送信できるようにSocketIoを保持する
+static SOCKET_IO: OnceLock<SocketIo> = OnceLock::new();
+
+const DEVICE_LOCKED_MESSAGE: &str = "この端末は一時停止中です";
+const BANNED_MESSAGE: &str = "この端末からの接続は禁止されています";
+const DEVICE_TOKEN_REQUIRED: &str = "このイベントに参加するには端末トークンが必要です";
+const DEVICE_TOKEN_REVOKED: &str = "端末トークンが無効になりました";
+const SELF_CHECKIN_INVALID_CODE: &str = "コードが正しくないか、有効期限が切れています";
+
+// 端末トークンの有効期限（時間）
+const DEFAULT_DEVICE_TOKEN_HOURS: i64 = 24;
+const MAX_DEVICE_TOKEN_HOURS: i64 = 24 * 30;
+
+pub fn get_socket_io() -> Option<SocketIo> {
+    SOCKET_IO.get().cloned()
+}
+
+// 保存済みのログをroomに送信
+async fn send_logs(socket: &SocketRef, room: &str, entries: &[LogEntry]) {
+    for log_entry in entries {
+        if let Err(e) = socket.within(room.to_string()).emit("activity_log", log_entry).await {
+            error!("Failed to broadcast log: {}", e);
+            get_metrics().record_broadcast_failure("activity_log");
+        }
+    }
+}
+
+async fn broadcast_log(socket: &SocketRef, room: &str, level: &str, message: String) {
+    let Some(log_entry) = get_event_store().update(room, |state| state.push_log(level, message)) else {
+        return;
+    };
+    send_logs(socket, room, &[log_entry]).await;
+}
+
+// 保存済みのログをソケットを使わずにroomに送信（管理APIなど）
+pub(crate) async fn broadcast_logs(room: &str, entries: &[LogEntry]) {
+    let Some(io) = get_socket_io() else {
+        return;
+    };
+    for log_entry in entries {
+        if let Err(e) = io.to(room.to_string()).emit("activity_log", log_entry).await {
+            error!("Failed to broadcast log: {}", e);
+            get_metrics().record_broadcast_failure("activity_log");
+        }
+    }
+}
+
+// ソケットを持たない処理（監視タスクなど）からのログ送信
+async fn broadcast_server_log(room: &str, level: &str, message: String) {
+    let Some(io) = get_socket_io() else {
+        return;
+    };
+    let Some(log_entry) = get_event_store().update(room, |state| state.push_log(level, message)) else {
+        return;
+    };
+    if let Err(e) = io.to(room.to_string()).emit("activity_log", &log_entry).await {
+        error!("Failed to broadcast log: {}", e);
+        get_metrics().record_broadcast_failure("activity_log");
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
-struct LogEntry {
-    timestamp: String,
-    level: String,
-    message: String,
+struct CheckinWindowStatus {
+    state: WindowState,
+    #[serde(flatten)]
+    window: crate::CheckinWindow,
+}
+
+fn checkin_window_status(state: &EventState) -> CheckinWindowStatus {
+    CheckinWindowStatus {
+        state: state.window_state(),
+        window: state.event.checkin_window.clone().unwrap_or_default(),
+    }
+}
+
+// 出席者リスト（2番目の引数は参加者IDごとの到着区分）と当日参加者リストを送信
+fn emit_current_lists(socket: &SocketRef, state: &EventState) {
+    let session = state.active_session();
+    let attendance = state.current_session();
+    let arrivals = state.arrival_categories(session.as_deref());
+
+    if let Err(e) = socket.emit("register_attendees_return", &(attendance.attendees, arrivals)) {
+        error!("Failed to send attendees data: {}", e);
+    }
+    if let Err(e) = socket.emit("register_ontheday_return", &attendance.ontheday) {
+        error!("Failed to send ontheday data: {}", e);
+    }
+}
+
+// 出席データの差分（追いつけない場合は全体）を送信元に送信
+fn emit_attendance(socket: &SocketRef, sync: &AttendanceSync) {
+    let result = match sync {
+        AttendanceSync::Deltas(deltas) => deltas
+            .iter()
+            .try_for_each(|delta| socket.emit(delta.kind.event_name(), delta)),
+        AttendanceSync::Snapshot(snapshot) => socket.emit("attendance_snapshot", snapshot),
+    };
+    if let Err(e) = result {
+        error!("Failed to send attendance changes: {}", e);
+    }
+}
+
+// 出席データの差分（追いつけない場合は全体）をroomに送信
+pub async fn broadcast_attendance(uuid: &str, sync: &AttendanceSync) {
+    let Some(io) = get_socket_io() else {
+        return;
+    };
+
+    match sync {
+        AttendanceSync::Deltas(deltas) => {
+            for delta in deltas {
+                if let Err(e) = io.to(uuid.to_string()).emit(delta.kind.event_name(), delta).await {
+                    error!("Failed to send attendance changes to room {}: {}", uuid, e);
+                    get_metrics().record_broadcast_failure(delta.kind.event_name());
+                }
+            }
+        }
+        AttendanceSync::Snapshot(snapshot) => {
+            if let Err(e) = io.to(uuid.to_string()).emit("attendance_snapshot", snapshot).await {
+                error!("Failed to send attendance snapshot to room {}: {}", uuid, e);
+                get_metrics().record_broadcast_failure("attendance_snapshot");
+            }
+        }
+    }
+}
+
+// 受付時間外の登録を拒否し、送信元に現在のリストを送り直す（拒否理由を返す）
+fn reject_outside_window(socket: &SocketRef, state: &EventState) -> Option<String> {
+    let window_state = state.window_state();
+    let message = window_state.rejection_reason()?;
+    warn!("Rejected registration outside check-in window for {:?}: {:?}", state.event.roomid, window_state);
+
+    if let Err(e) = socket.emit("checkin_rejected", message) {
+        error!("Failed to send error message: {}", e);
+    }
+    emit_current_lists(socket, state);
+
+    Some(message.to_string())
+}
+
+// 受付時間の開始・終了を監視し、状態や受付時間の設定が変わったらroomに通知する
+async fn watch_checkin_windows() {
+    let mut last_statuses: HashMap<String, (WindowState, crate::CheckinWindow)> = HashMap::new();
+    let mut ticker = tokio::time::interval(std::time::Duration::from_secs(5));
+
+    loop {
+        ticker.tick().await;
+
+        let store = get_event_store();
+        for uuid in store.ids() {
+            let Some(status) = store.read(&uuid, checkin_window_status) else {
+                continue;
+            };
+
+            // 受付時間を一度も設定していないイベントには通知しない（解除した場合は通知する）
+            let previous = last_statuses.get(&uuid).cloned();
+            if previous.is_none() && status.window == crate::CheckinWindow::default() {
+                continue;
+            }
+            if previous.as_ref().is_some_and(|(state, window)| *state == status.state && *window == status.window) {
+                continue;
+            }
+            last_statuses.insert(uuid.clone(), (status.state, status.window.clone()));
+
+            if let Some(io) = get_socket_io() {
+                if let Err(e) = io.to(uuid.clone()).emit("checkin_window", &status).await {
+                    error!("Failed to send check-in window to room {}: {}", uuid, e);
+                    get_metrics().record_broadcast_failure("checkin_window");
+                }
+            }
+
+            // 初回の確認時と、状態が変わらない設定の変更は通知のみ
+            if previous.is_some_and(|(state, _)| state != status.state) {
+                let message = match status.state {
+                    WindowState::Open => "受付を開始しました",
+                    WindowState::Closed => "受付を終了しました",
+                    WindowState::NotYetOpen => "受付開始前に戻りました",
+                };
+                broadcast_server_log(&uuid, "server", message.to_string()).await;
+            }
+        }
+    }
+}
+
+
+
+
+
+#[cfg(feature = "debug")]
+async fn on_connect(socket: SocketRef, TryData(data): TryData<String>, ack: AckSender) {
+    info!("Client connected: {}", socket.id);
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let return_data = get_event_store().read(&data, |state| state.event.clone());
+
+    if let Err(e) = socket.emit("debug_init_data", &return_data) {
+        error!("Failed to send initial data: {}", e);
+    }
+
+    match return_data {
+        Some(_) => reply(ack, AckResponse::ok(())),
+        None => reply(ack, AckResponse::event_not_found()),
+    }
 }
 
-fn create_log_entry(level: &str, message: String) -> LogEntry {
-    LogEntry {
-        timestamp: Local::now().format("%H:%M:%S").to_string(),
-        level: level.to_string(),
-        message,
+async fn on_disconnect(socket: SocketRef) {
+    info!("Client disconnected: {}", socket.id);
+
+    // 接続していたroomの端末一覧を更新
+    let socket_id = socket.id.to_string();
+    let store = get_event_store();
+    for uuid in store.ids() {
+        let Some(Some((device, devices))) = store.update(&uuid, |state| {
+            state.disconnect_device(&socket_id).map(|device| (device, state.devices()))
+        }) else {
+            continue;
+        };
+
+        broadcast_devices(&uuid, &devices);
+        broadcast_server_log(&uuid, "server", format!("端末が切断しました ({})", device_label(&device))).await;
+    }
+}
+
+// ログに表示する端末名（名前がなければソケットIDの先頭8文字）
+fn device_label(device: &DeviceInfo) -> String {
+    if device.device_name.is_empty() {
+        format!("ID: {}", &device.socket_id[..8])
+    } else {
+        device.device_name.clone()
+    }
+}
+
+// 接続元のIPアドレス
+fn client_ip(socket: &SocketRef) -> Option<String> {
+    socket
+        .req_parts()
+        .extensions
+        .get::<axum::extract::ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip().to_string())
+}
+
+// サーバーを起動したPC自身からの接続か
+pub(crate) fn is_loopback(ip: &str) -> bool {
+    ip.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+// ログに表示するソケットの端末名
+fn socket_label(socket: &SocketRef, uuid: &str) -> String {
+    let socket_id = socket.id.to_string();
+    get_event_store()
+        .read(uuid, |state| state.devices.get(&socket_id).map(device_label))
+        .flatten()
+        .unwrap_or_else(|| format!("ID: {}", &socket_id[..8]))
+}
+
+// イベントの送信回数と内容の大きさを確認する
+// 上限を超えた場合は警告をログに保存する（送信回数はこの期間で最初の1回だけ）
+async fn limit(socket: &SocketRef, uuid: &str, event: &str, validation: Result<(), String>) -> Result<(), AckResponse<()>> {
+    let by_socket = get_socket_limiter().check(&socket.id.to_string());
+    // 同じPCからの接続（ループバック）はIPアドレスでは制限しない
+    let by_ip = match client_ip(socket) {
+        Some(ip) if !is_loopback(&ip) => get_ip_limiter().check(&ip),
+        _ => RateLimit::Allowed,
+    };
+
+    match (by_socket, by_ip) {
+        (RateLimit::Allowed, RateLimit::Allowed) => {}
+        (RateLimit::Exceeded { first }, _) | (_, RateLimit::Exceeded { first }) => {
+            warn!("Rate limit exceeded for {} ({}): {}", socket.id, event, uuid);
+            if first {
+                let message = format!("送信回数が上限を超えたため {} を拒否しました ({})", event, socket_label(socket, uuid));
+                broadcast_server_log(uuid, "warning", message).await;
+            }
+            return Err(AckResponse::error(
+                ErrorCode::RateLimited,
+                "送信回数が多すぎます。しばらく待ってから再度お試しください",
+            ));
+        }
+    }
+
+    if let Err(message) = validation {
+        warn!("Rejected oversized {} from {}: {}", event, socket.id, message);
+        broadcast_server_log(uuid, "warning", format!("{} を拒否しました: {} ({})", event, message, socket_label(socket, uuid))).await;
+        return Err(AckResponse::error(ErrorCode::TooLarge, message));
+    }
+    Ok(())
+}
+
+// joinしたイベントに対してのみ操作・同期を受け付ける
+// （他のイベントのIDを指定して、そのイベントのデータを取得したりroomに送信させたりできないようにする）
+fn require_joined(socket: &SocketRef, uuid: &str) -> Result<(), AckResponse<()>> {
+    match get_event_store().read(uuid, |state| state.is_joined(&socket.id.to_string())) {
+        None => Err(AckResponse::event_not_found()),
+        Some(false) => {
+            warn!("Rejected request from {} for event it has not joined: {}", socket.id, uuid);
+            Err(AckResponse::error(ErrorCode::NotJoined, "このイベントに参加していません"))
+        }
+        Some(true) => Ok(()),
+    }
+}
+
+// 状態を変更するイベントを受け付けるかどうかを確認する
+async fn guard(socket: &SocketRef, uuid: &str, event: &str, validation: Result<(), String>) -> Result<(), AckResponse<()>> {
+    limit(socket, uuid, event, validation).await?;
+    require_joined(socket, uuid)
+}
+
+// ソケットが参加しているイベント
+#[cfg(feature = "debug")]
+fn joined_events(socket: &SocketRef) -> Vec<String> {
+    let socket_id = socket.id.to_string();
+    let store = get_event_store();
+    store
+        .ids()
+        .into_iter()
+        .filter(|uuid| store.read(uuid, |state| state.is_joined(&socket_id)).unwrap_or(false))
+        .collect()
+}
+
+// 端末一覧を送信（IPアドレスや端末トークン名を含むため、roomではなく信頼できるモニタにのみ送る）
+fn broadcast_devices(uuid: &str, devices: &[DeviceInfo]) {
+    let monitors = get_event_store().read(uuid, trusted_monitor_sockets).unwrap_or_default();
+    emit_to_monitors(&monitors, "devices_update", &devices);
+}
+
+// 最終操作時刻・登録件数の変更をまとめて通知する
+async fn watch_devices() {
+    let mut ticker = tokio::time::interval(std::time::Duration::from_secs(5));
+
+    loop {
+        ticker.tick().await;
+
+        let store = get_event_store();
+        for uuid in store.ids() {
+            if let Some(Some(devices)) = store.update(&uuid, |state| state.take_devices_changed()) {
+                broadcast_devices(&uuid, &devices);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "debug")]
+async fn on_new_message(socket: SocketRef, TryData(data): TryData<String>, ack: AckSender) {
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    debug!("Received message from {}: {}", socket.id, data);
+    
+    // 同じイベントに参加している他のクライアントにのみ送信する
+    let rooms = joined_events(&socket);
+    if rooms.is_empty() {
+        return reply(ack, AckResponse::<()>::error(ErrorCode::NotJoined, "イベントに参加していません"));
+    }
+    if let Err(e) = socket.to(rooms).emit("debug_new_msg", &data).await {
+        error!("Failed to broadcast message: {}", e);
+    }
+    reply(ack, AckResponse::ok(()));
+}
+
+// 登録されていないイベントは拒否する
+async fn on_unknown_event(socket: SocketRef, Event(event): Event, ack: AckSender) {
+    warn!("Rejected unknown event from {}: {}", socket.id, event);
+    reply(ack, AckResponse::<()>::error(ErrorCode::UnknownEvent, format!("不明なイベントです: {}", event)));
+}
+
+// join にはイベントIDのみ、または端末の情報を含めて送信できる
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum JoinData {
+    Uuid(String),
+    Device {
+        uuid: String,
+        #[serde(default)]
+        role: DeviceRole,
+        #[serde(default)]
+        device_name: String,
+        // 端末トークン（指定した場合、端末名と役割はトークンのものを使う）
+        #[serde(default)]
+        token: Option<String>,
+    },
+}
+
+// 指定したソケットに理由を通知してから切断する
+fn disconnect_socket(socket_id: &str, reason: &str) {
+    let Some(socket) = get_socket_io()
+        .zip(socket_id.parse::<Sid>().ok())
+        .and_then(|(io, sid)| io.get_socket(sid))
+    else {
+        return;
+    };
+
+    if let Err(e) = socket.emit("kicked", reason) {
+        error!("Failed to send kick reason: {}", e);
+    }
+    if let Err(e) = socket.disconnect() {
+        error!("Failed to disconnect socket {}: {}", socket_id, e);
+    }
+}
+
+// 端末を切断する（切断の記録は on_disconnect で行う）
+pub async fn kick_device(uuid: &str, socket_id: &str) -> Result<DeviceInfo, String> {
+    let device = get_event_store()
+        .read(uuid, |state| state.devices.get(socket_id).filter(|device| device.online).cloned())
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?
+        .ok_or_else(|| DEVICE_NOT_FOUND.to_string())?;
+
+    broadcast_server_log(uuid, "warning", format!("管理者が端末を切断しました ({})", device_label(&device))).await;
+    disconnect_socket(socket_id, "管理者により切断されました");
+    Ok(device)
+}
+
+// 端末を一時停止・再開する
+pub async fn lock_device(uuid: &str, socket_id: &str, locked: bool) -> Result<DeviceInfo, String> {
+    let (device, devices) = get_event_store().try_update(uuid, |state| {
+        let device = state.set_device_locked(socket_id, locked)?;
+        Ok((device, state.devices()))
+    })?;
+
+    if let Some(socket) = get_socket_io()
+        .zip(socket_id.parse::<Sid>().ok())
+        .and_then(|(io, sid)| io.get_socket(sid))
+    {
+        if let Err(e) = socket.emit("device_locked", &locked) {
+            error!("Failed to send lock state: {}", e);
+        }
+    }
+
+    broadcast_devices(uuid, &devices);
+    let message = if locked {
+        format!("端末を一時停止しました ({})", device_label(&device))
+    } else {
+        format!("端末の一時停止を解除しました ({})", device_label(&device))
+    };
+    broadcast_server_log(uuid, "warning", message).await;
+    Ok(device)
+}
+
+// IPアドレスをイベントの終了まで禁止し、接続中の端末を切断する
+pub async fn ban_ip(uuid: &str, ip: &str) -> Result<Vec<String>, String> {
+    let targets = get_event_store()
+        .update(uuid, |state| state.ban_ip(ip))
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())??;
+
+    broadcast_server_log(uuid, "warning", format!("IPアドレス {} からの接続を禁止しました", ip)).await;
+    for socket_id in &targets {
+        disconnect_socket(socket_id, BANNED_MESSAGE);
+    }
+    Ok(targets)
+}
+
+// 端末トークンを発行する（有効期限の指定がなければ DEFAULT_DEVICE_TOKEN_HOURS 時間）
+pub async fn issue_device_token(
+    uuid: &str,
+    device_name: &str,
+    role: DeviceRole,
+    valid_hours: Option<i64>,
+) -> Result<DeviceToken, String> {
+    let device_name = device_name.trim();
+    if device_name.is_empty() {
+        return Err("端末名を指定してください".to_string());
+    }
+    validate_lengths([&device_name.to_string()], MAX_NAME_LENGTH)?;
+    let valid_hours = valid_hours.unwrap_or(DEFAULT_DEVICE_TOKEN_HOURS);
+    if !(1..=MAX_DEVICE_TOKEN_HOURS).contains(&valid_hours) {
+        return Err(format!("有効期限は1〜{}時間で指定してください", MAX_DEVICE_TOKEN_HOURS));
+    }
+
+    let token = get_event_store()
+        .update(uuid, |state| state.issue_device_token(device_name, role, chrono::Duration::hours(valid_hours)))
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+    info!("Issued device token {} for {}", token.id, uuid);
+    broadcast_server_log(uuid, "server", format!("端末トークンを発行しました ({})", token.device_name)).await;
+    Ok(token)
+}
+
+// 端末トークンを無効にし、そのトークンで接続中の端末を切断する（他の端末には影響しない）
+pub async fn revoke_device_token(uuid: &str, token_id: &str) -> Result<DeviceToken, String> {
+    let (token, targets) = get_event_store().try_update(uuid, |state| state.revoke_device_token(token_id))?;
+    info!("Revoked device token {} for {}", token.id, uuid);
+    broadcast_server_log(uuid, "warning", format!("端末トークンを無効にしました ({})", token.device_name)).await;
+    for socket_id in &targets {
+        disconnect_socket(socket_id, DEVICE_TOKEN_REVOKED);
+    }
+    Ok(token)
+}
+
+// 端末トークンを持たない端末の参加を禁止する（サーバーを起動したPCを除く）
+pub fn set_require_device_token(uuid: &str, required: bool) -> Result<(), String> {
+    get_event_store()
+        .update(uuid, |state| state.event.require_device_token = required)
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+    info!("Set require_device_token for {}: {}", uuid, required);
+    Ok(())
+}
+
+// 学生のスマートフォンからのセルフ出席登録を受け付けるかどうか
+pub fn enable_self_checkin(uuid: &str, enabled: bool) -> Result<(), String> {
+    get_event_store()
+        .update(uuid, |state| state.event.self_checkin = enabled)
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+    info!("Set self_checkin for {}: {}", uuid, enabled);
+    Ok(())
+}
+
+// カード番号の対応表を読み込む（replace を指定した場合は以前の対応表を破棄する）
+pub async fn import_card_ids(uuid: &str, csv: &str, replace: bool) -> Result<CardImport, String> {
+    let (result, card_reader) = get_event_store()
+        .update(uuid, |state| (state.import_card_ids(csv, replace), !state.card_ids.is_empty()))
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+    info!("Imported {} card ids for {} ({} skipped)", result.imported, uuid, result.skipped);
+    let message = format!("カード番号の対応表を読み込みました（{}件）", result.imported);
+    broadcast_server_log(uuid, "server", message).await;
+
+    // 接続中の端末にも、名簿にない入力をサーバーに送るかどうかを伝える
+    if let Some(io) = get_socket_io() {
+        if let Err(e) = io.to(uuid.to_string()).emit("card_reader", &card_reader).await {
+            error!("Failed to send card reader state to room {}: {}", uuid, e);
+            get_metrics().record_broadcast_failure("card_reader");
+        }
+    }
+    Ok(result)
+}
+
+// QRコードを読み取った端末からのみ出席登録を受け付けるかどうか
+pub fn enable_qr_checkin(uuid: &str, enabled: bool) -> Result<(), String> {
+    let current = get_event_store()
+        .update(uuid, |state| {
+            state.event.qr_checkin = enabled;
+            current_qr_token(state).map(|token| (token, trusted_monitor_sockets(state)))
+        })
+        .ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+    info!("Set qr_checkin for {}: {}", uuid, enabled);
+    // 次の切り替えを待たずにモニタへ表示する
+    if let Some((token, monitors)) = current {
+        emit_to_monitors(&monitors, "qr_token", &token);
+    }
+    Ok(())
+}
+
+fn admin_error_code(message: &str) -> ErrorCode {
+    if message == EVENT_NOT_FOUND {
+        ErrorCode::EventNotFound
+    } else if message == CANNOT_BAN_LOOPBACK {
+        ErrorCode::Forbidden
+    } else {
+        ErrorCode::DeviceNotFound
+    }
+}
+
+// 管理用のイベントはroomに参加している主催者のモニタからのみ受け付ける
+fn require_monitor(socket: &SocketRef, uuid: &str) -> Result<(), AckResponse<()>> {
+    match get_event_store().read(uuid, |state| state.is_trusted_monitor(&socket.id.to_string())) {
+        None => Err(AckResponse::event_not_found()),
+        Some(false) => Err(AckResponse::error(
+            ErrorCode::Forbidden,
+            "サーバーを起動したPC、または端末トークンで参加したモニタ以外からは操作できません",
+        )),
+        Some(true) => Ok(()),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct AdminDeviceData {
+    uuid: String,
+    socket_id: String,
+    #[serde(default)]
+    locked: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct AdminBanData {
+    uuid: String,
+    ip: String,
+}
+
+async fn admin_kick(socket: SocketRef, TryData(data): TryData<AdminDeviceData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("admin_kick");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    if let Err(response) = guard(&socket, &data.uuid, "admin_kick", Ok(())).await {
+        return reply(ack, response);
+    }
+    info!("Received admin_kick from {}: {:?}", socket.id, data);
+    if let Err(response) = require_monitor(&socket, &data.uuid) {
+        return reply(ack, response);
+    }
+
+    match kick_device(&data.uuid, &data.socket_id).await {
+        Ok(device) => reply(ack, AckResponse::ok(device)),
+        Err(message) => reply(ack, AckResponse::<()>::error(admin_error_code(&message), message)),
+    }
+}
+
+async fn admin_lock(socket: SocketRef, TryData(data): TryData<AdminDeviceData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("admin_lock");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    if let Err(response) = guard(&socket, &data.uuid, "admin_lock", Ok(())).await {
+        return reply(ack, response);
+    }
+    info!("Received admin_lock from {}: {:?}", socket.id, data);
+    if let Err(response) = require_monitor(&socket, &data.uuid) {
+        return reply(ack, response);
+    }
+
+    match lock_device(&data.uuid, &data.socket_id, data.locked).await {
+        Ok(device) => reply(ack, AckResponse::ok(device)),
+        Err(message) => reply(ack, AckResponse::<()>::error(admin_error_code(&message), message)),
+    }
+}
+
+async fn admin_ban(socket: SocketRef, TryData(data): TryData<AdminBanData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("admin_ban");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_lengths([&data.ip], MAX_NAME_LENGTH);
+    if let Err(response) = guard(&socket, &data.uuid, "admin_ban", validation).await {
+        return reply(ack, response);
+    }
+    info!("Received admin_ban from {}: {:?}", socket.id, data);
+    if let Err(response) = require_monitor(&socket, &data.uuid) {
+        return reply(ack, response);
+    }
+
+    match ban_ip(&data.uuid, &data.ip).await {
+        Ok(disconnected) => reply(ack, AckResponse::ok(disconnected)),
+        Err(message) => reply(ack, AckResponse::<()>::error(admin_error_code(&message), message)),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    // 動作ログに保存するときのレベル
+    fn log_level(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "error",
+        }
+    }
+}
+
+// 主催者から全端末へのお知らせ（表示時間の指定がなければ閉じるまで表示）
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Announcement {
+    pub message: String,
+    #[serde(default)]
+    pub severity: Severity,
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+}
+
+// roomの全端末にお知らせを送信し、動作ログに保存する
+pub async fn announce(uuid: &str, announcement: Announcement) -> Result<Announcement, String> {
+    let message = announcement.message.trim();
+    if message.is_empty() {
+        return Err("お知らせの内容が空です".to_string());
+    }
+    let announcement = Announcement {
+        message: message.to_string(),
+        ..announcement
+    };
+
+    let Some(io) = get_socket_io() else {
+        return Err("サーバーが起動していません".to_string());
+    };
+    if !get_event_store().contains(uuid) {
+        return Err(EVENT_NOT_FOUND.to_string());
+    }
+
+    broadcast_server_log(uuid, announcement.severity.log_level(), format!("お知らせ: {}", announcement.message)).await;
+    if let Err(e) = io.to(uuid.to_string()).emit("announcement", &announcement).await {
+        error!("Failed to send announcement to room {}: {}", uuid, e);
+        get_metrics().record_broadcast_failure("announcement");
+    }
+    Ok(announcement)
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct AnnounceData {
+    uuid: String,
+    #[serde(flatten)]
+    announcement: Announcement,
+}
+
+async fn announce_event(socket: SocketRef, TryData(data): TryData<AnnounceData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("announce");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_lengths([&data.announcement.message], MAX_MESSAGE_LENGTH);
+    if let Err(response) = guard(&socket, &data.uuid, "announce", validation).await {
+        return reply(ack, response);
+    }
+    info!("Received announce from {}: {:?}", socket.id, data);
+    if let Err(response) = require_monitor(&socket, &data.uuid) {
+        return reply(ack, response);
+    }
+
+    match announce(&data.uuid, data.announcement).await {
+        Ok(announcement) => reply(ack, AckResponse::ok(announcement)),
+        Err(message) if message == EVENT_NOT_FOUND => reply(ack, AckResponse::event_not_found()),
+        Err(message) => reply(ack, AckResponse::invalid_request(message)),
+    }
+}
+
+// 参加コードを発行し直し、roomに新しいコードを通知する
+pub async fn reissue_join_code(uuid: &str) -> Result<String, String> {
+    let code = issue_join_code(uuid)?;
+    broadcast_server_log(uuid, "warning", "参加コードを発行しました（以前のコードでは参加できなくなります）".to_string()).await;
+    if let Some(io) = get_socket_io() {
+        if let Err(e) = io.to(uuid.to_string()).emit("join_code_update", &code).await {
+            error!("Failed to send join code to room {}: {}", uuid, e);
+            get_metrics().record_broadcast_failure("join_code_update");
+        }
+    }
+    Ok(code)
+}
+
+async fn regenerate_join_code(socket: SocketRef, TryData(data): TryData<String>, ack: AckSender) {
+    let _timer = HandlerTimer::start("regenerate_join_code");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    if let Err(response) = guard(&socket, &data, "regenerate_join_code", Ok(())).await {
+        return reply(ack, response);
+    }
+    info!("Received regenerate_join_code from {}: {}", socket.id, data);
+    if let Err(response) = require_monitor(&socket, &data) {
+        return reply(ack, response);
+    }
+
+    match reissue_join_code(&data).await {
+        Ok(code) => reply(ack, AckResponse::ok(code)),
+        Err(_) => reply(ack, AckResponse::event_not_found()),
+    }
+}
+
+async fn join_data(socket: SocketRef, TryData(data): TryData<JoinData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("join");
+    let (data, role, device_name, token) = match data {
+        Ok(JoinData::Uuid(uuid)) => (uuid, DeviceRole::Unknown, String::new(), None),
+        Ok(JoinData::Device { uuid, role, device_name, token }) => (uuid, role, device_name.trim().to_string(), token),
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_lengths([&device_name].into_iter().chain(token.as_ref()), MAX_NAME_LENGTH);
+    if let Err(response) = limit(&socket, &data, "join", validation).await {
+        return reply(ack, response);
+    }
+    info!("Received join from {}: {} ({:?})", socket.id, data, role);
+    
+    if data.is_empty() || data == "undefined" || data == "null" {
+        warn!("Invalid UUID received: {}", data);
+        if let Err(e) = socket.emit("join_error", "無効なUUIDです") {
+            error!("Failed to send error message: {}", e);
+        }
+        reply(ack, AckResponse::invalid_request("無効なUUIDです"));
+        return;
+    }
+
+    // 参加コードで参加した場合はイベントIDに置き換える（以降のイベントはイベントIDで送信する）
+    let data = get_event_store().resolve(&data).unwrap_or(data);
+    
+    // 端末を登録し、保存された設定を反映したイベントデータを取得
+    let now = chrono::Local::now().to_rfc3339();
+    let device = DeviceInfo {
+        socket_id: socket.id.to_string(),
+        role,
+        device_name,
+        ip: client_ip(&socket),
+        connected_since: now.clone(),
+        last_activity: now,
+        checkins: 0,
+        online: true,
+        locked: false,
+        token_id: None,
+    };
+    let local = device.ip.as_deref().is_some_and(is_loopback);
+    let joined = get_event_store().update(&data, |state| {
+        if device.ip.as_deref().is_some_and(|ip| state.is_ip_banned(ip)) {
+            return Err((ErrorCode::Banned, BANNED_MESSAGE.to_string()));
+        }
+        let mut device = device.clone();
+        match token.as_deref() {
+            Some(token) => {
+                let token = state
+                    .validate_device_token(token, chrono::Local::now().fixed_offset())
+                    .map_err(|message| (ErrorCode::InvalidToken, message))?;
+                device.device_name = token.device_name;
+                device.role = token.role;
+                device.token_id = Some(token.id);
+            }
+            // トークンが必須のイベントでも、サーバーを起動したPCからは参加できる
+            None if state.event.require_device_token && !local => {
+                return Err((ErrorCode::InvalidToken, DEVICE_TOKEN_REQUIRED.to_string()));
+            }
+            None => {}
+        }
+        state.connect_device(device.clone());
+        let locked = state.is_device_locked(&device.socket_id);
+        let code = current_self_checkin_code(state).filter(|_| receives_self_checkin_code(state, &device));
+        let qr_token = current_qr_token(state).filter(|_| receives_self_checkin_code(state, &device));
+        Ok((device, state.event_with_settings(), checkin_window_status(state), state.logs.clone(), state.devices(), locked, code, qr_token))
+    });
+    let Some(joined) = joined else {
+        warn!("No data found for event: {}", data);
+        // UUIDが存在しない場合、エラーを返す
+        if let Err(e) = socket.emit("join_error", EVENT_NOT_FOUND) {
+            error!("Failed to send error message: {}", e);
+        }
+        reply(ack, AckResponse::event_not_found());
+        return;
+    };
+    let (device, final_data, window_status, logs, devices, locked, self_checkin_code, qr_token) = match joined {
+        Ok(joined) => joined,
+        Err((code, message)) => {
+            warn!("Rejected join from {} ({:?}): {}", socket.id, device.ip, message);
+            if let Err(e) = socket.emit("join_error", &message) {
+                error!("Failed to send error message: {}", e);
+            }
+            reply(ack, AckResponse::<()>::error(code, message));
+            if code == ErrorCode::Banned {
+                if let Err(e) = socket.disconnect() {
+                    error!("Failed to disconnect socket: {}", e);
+                }
+            }
+            return;
+        }
+    };
+
+    debug!("Returning data: {:?}", final_data);
+
+    // UUIDをroomとして使用してソケットを参加させる
+    let room_name = data.clone();
+    socket.join(room_name.clone());
+    info!("Socket {} joined room: {}", socket.id, room_name);
+
+    // クライアント接続のログをブロードキャスト
+    let socket_clone = socket.clone();
+    let room_clone = room_name.clone();
+    let label = device_label(&device);
+    tokio::spawn(async move {
+        broadcast_log(&socket_clone, &room_clone, "server", format!("クライアントが接続しました ({})", label)).await;
+    });
+    broadcast_devices(&room_name, &devices);
+
+    // 最新の設定を反映したデータをクライアントに送信
+    if let Err(e) = socket.emit("join_return", &final_data) {
+        error!("Failed to send initial data: {}", e);
+    }
+
+    if let Err(e) = socket.emit("checkin_window", &window_status) {
+        error!("Failed to send check-in window: {}", e);
+    }
+
+    // これまでのログを接続したクライアントに送信
+    if let Err(e) = socket.emit("activity_log_history", &logs) {
+        error!("Failed to send activity log history: {}", e);
+    }
+
+    // 一時停止中の端末が再接続した場合は停止状態を伝える
+    if locked {
+        if let Err(e) = socket.emit("device_locked", &true) {
+            error!("Failed to send lock state: {}", e);
+        }
+    }
+
+    // セルフ出席登録のコードを表示するモニタには、次の切り替えを待たずに現在のコードを送る
+    if let Some(code) = self_checkin_code {
+        if let Err(e) = socket.emit("self_checkin_code", &code) {
+            error!("Failed to send self check-in code: {}", e);
+        }
+    }
+    if let Some(token) = qr_token {
+        if let Err(e) = socket.emit("qr_token", &token) {
+            error!("Failed to send QR token: {}", e);
+        }
+    }
+
+    reply(ack, AckResponse::ok(room_name));
+}
+
+async fn sync_all_data(socket: SocketRef, TryData(data): TryData<String>, ack: AckSender) {
+    let _timer = HandlerTimer::start("sync_all_data");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    info!("Received sync_all_data from {}: {:?}", socket.id, data);
+    
+    if data.is_empty() || data == "undefined" || data == "null" {
+        warn!("Invalid UUID received in sync_all_data: {}", data);
+        reply(ack, AckResponse::invalid_request("無効なUUIDです"));
+        return;
+    }
+    
+    if let Err(response) = require_joined(&socket, &data) {
+        return reply(ack, response);
+    }
+
+    // ここで全データを同期するロジックを実装
+    let Some(state) = get_event_store().get(&data) else {
+        warn!("No data found for event: {}", data);
+        reply(ack, AckResponse::event_not_found());
+        return;
+    };
+    debug!("Returning data: {:?}", state.current_session());
+
+    emit_current_lists(&socket, &state);
+
+    if let Err(e) = socket.emit("attendance_records_return", &state.current_session().records) {
+        error!("Failed to send sync_all_data: {}", e);
+    }
+
+    // 以降は差分で受け取れるように、現在のバージョンを送信
+    if let Err(e) = socket.emit("attendance_version", &state.version) {
+        error!("Failed to send attendance version: {}", e);
+    }
+
+    reply(ack, AckResponse::ok(state.version));
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct SyncSinceData {
+    uuid: String,
+    version: u64,
+}
+
+// 端末が持っているバージョン以降の差分を返す
+async fn sync_since(socket: SocketRef, TryData(data): TryData<SyncSinceData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("sync_since");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    info!("Received sync_since from {}: {:?}", socket.id, data);
+    if let Err(response) = require_joined(&socket, &data.uuid) {
+        return reply(ack, response);
+    }
+
+    let Some((sync, version)) = get_event_store().read(&data.uuid, |state| (state.sync_since(data.version), state.version)) else {
+        warn!("No data found for event: {}", data.uuid);
+        reply(ack, AckResponse::event_not_found());
+        return;
+    };
+    emit_attendance(&socket, &sync);
+    reply(ack, AckResponse::ok(version));
+}
+
+
+
+
+#[derive(Deserialize, Serialize, Debug)]
+struct TodayData {
+    uuid: String,
+    id: String,
+    // モニタのQRコードから読み取ったトークン（QRコードによる出席登録が有効なイベントのみ）
+    #[serde(default)]
+    qr_token: Option<String>,
+}
+
+// 登録結果をエラーコードに変換（登録済みはエラーとしない）
+pub(crate) fn outcome_error(outcome: &CheckinOutcome) -> Option<(ErrorCode, String)> {
+    match outcome {
+        CheckinOutcome::Registered { .. } | CheckinOutcome::AlreadyRegistered { .. } => None,
+        CheckinOutcome::UnknownId => Some((ErrorCode::UnknownId, "出席者リストに含まれていません".to_string())),
+        CheckinOutcome::Rejected { reason } => Some((ErrorCode::OutsideWindow, reason.clone())),
+        CheckinOutcome::LimitReached { limit } => {
+            Some((ErrorCode::OnthedayLimitReached, format!("当日参加者の上限（{}人）に達しました", limit)))
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct SelfCheckInData {
+    uuid: String,
+    id: String,
+    code: String,
+}
+
+// 学生が自分のスマートフォンから出席登録する（joinせずに送信する）
+// モニタに表示されたコードが必要なため、教室の外にいる学生の代わりに登録することはできない
+async fn self_check_in(socket: SocketRef, TryData(data): TryData<SelfCheckInData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("self_check_in");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_lengths([&data.id, &data.code], MAX_ID_LENGTH);
+    if let Err(response) = limit(&socket, &data.uuid, "self_check_in", validation).await {
+        return reply(ack, response);
+    }
+    let id = data.id.trim().to_string();
+    if id.is_empty() {
+        return reply(ack, AckResponse::invalid_request("IDを入力してください"));
+    }
+    info!("Received self_check_in from {} for {}: {}", socket.id, data.uuid, redact_id(&id));
+
+    let ip = client_ip(&socket);
+    let result = get_event_store().update(&data.uuid, |state| {
+        if !state.event.self_checkin {
+            return Err((ErrorCode::Forbidden, "このイベントではセルフ出席登録を受け付けていません".to_string()));
+        }
+        if ip.as_deref().is_some_and(|ip| state.is_ip_banned(ip)) {
+            return Err((ErrorCode::Banned, BANNED_MESSAGE.to_string()));
+        }
+        if !verify_self_checkin_code(&state.self_checkin_secret, &data.code, SystemTime::now()) {
+            return Err((ErrorCode::InvalidCode, SELF_CHECKIN_INVALID_CODE.to_string()));
+        }
+        let version = state.version;
+        let outcome = state
+            .self_check_in(ip.as_deref(), &id, chrono::Local::now().fixed_offset())
+            .map_err(|message| (ErrorCode::Forbidden, message))?;
+        let mut logs = Vec::new();
+        if matches!(outcome, CheckinOutcome::Registered { .. }) {
+            get_metrics().record_checkins(&data.uuid, 1);
+            logs.push(state.push_log("info", format!("セルフ出席登録: {} が出席しました", id)));
+        }
+        Ok((outcome, logs, state.sync_since(version)))
+    });
+
+    let (outcome, logs, sync) = match result {
+        None => return reply(ack, AckResponse::event_not_found()),
+        Some(Err((code, message))) => {
+            warn!("Rejected self_check_in from {} ({:?}): {}", socket.id, ip, message);
+            // コードの誤りや同じ端末からの複数登録は代理登録の可能性があるためモニタに表示する
+            if matches!(code, ErrorCode::InvalidCode | ErrorCode::Forbidden) {
+                let log = format!("セルフ出席登録を拒否しました: {} ({})", id, message);
+                broadcast_server_log(&data.uuid, "warning", log).await;
+            }
+            return reply(ack, AckResponse::<()>::error(code, message));
+        }
+        Some(Ok(result)) => result,
+    };
+
+    broadcast_logs(&data.uuid, &logs).await;
+    broadcast_attendance(&data.uuid, &sync).await;
+
+    let error = outcome_error(&outcome);
+    let result = CheckinResult { id, outcome };
+    match error {
+        Some((code, message)) => reply(ack, AckResponse::<()>::error(code, message)),
+        None => reply(ack, AckResponse::ok(result)),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct QrCheckInData {
+    uuid: String,
+    id: String,
+    qr_token: String,
+}
+
+// 学生がモニタのQRコードを読み取ったスマートフォンから出席登録する（セルフ出席登録と同じくjoinせずに送信する）
+async fn qr_check_in(socket: SocketRef, TryData(data): TryData<QrCheckInData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("qr_check_in");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_lengths([&data.id, &data.qr_token], MAX_ID_LENGTH);
+    if let Err(response) = limit(&socket, &data.uuid, "qr_check_in", validation).await {
+        return reply(ack, response);
+    }
+    if data.id.trim().is_empty() {
+        return reply(ack, AckResponse::invalid_request("IDを入力してください"));
+    }
+    info!("Received qr_check_in from {} for {}: {}", socket.id, data.uuid, redact_id(&data.id));
+
+    let ip = client_ip(&socket);
+    let client = ip.clone().unwrap_or_else(|| socket.id.to_string());
+    let result = get_event_store().update(&data.uuid, |state| {
+        if !state.event.qr_checkin {
+            return Err((ErrorCode::Forbidden, "このイベントではQRコードによる出席登録を受け付けていません".to_string()));
+        }
+        if ip.as_deref().is_some_and(|ip| state.is_ip_banned(ip)) {
+            return Err((ErrorCode::Banned, BANNED_MESSAGE.to_string()));
+        }
+        let id = state.resolve_card(data.id.trim());
+        // 再入室も新たな出席登録として扱う
+        let checking_in = if state.registered_at(&id).is_none() || state.is_checked_out(&id) {
+            vec![id.clone()]
+        } else {
+            Vec::new()
+        };
+        check_qr_checkin(state, &socket.id.to_string(), &client, Some(&data.qr_token), &checking_in)
+            .map_err(|reason| (ErrorCode::InvalidQrToken, reason))?;
+
+        let version = state.version;
+        let outcome = state.check_in_at(&id, chrono::Local::now().fixed_offset(), false);
+        let mut logs = Vec::new();
+        if matches!(outcome, CheckinOutcome::Registered { .. }) {
+            get_metrics().record_checkins(&data.uuid, 1);
+            logs.push(state.push_log("info", format!("QRコード出席登録: {} が出席しました", id)));
+        }
+        Ok((id, outcome, logs, state.sync_since(version)))
+    });
+
+    let (id, outcome, logs, sync) = match result {
+        None => return reply(ack, AckResponse::event_not_found()),
+        Some(Err((code, message))) => {
+            warn!("Rejected qr_check_in from {} ({:?}): {}", socket.id, ip, message);
+            if code == ErrorCode::InvalidQrToken {
+                let log = format!("QRコードによる出席登録を拒否しました: {} ({})", data.id.trim(), message);
+                broadcast_server_log(&data.uuid, "warning", log).await;
+            }
+            return reply(ack, AckResponse::<()>::error(code, message));
+        }
+        Some(Ok(result)) => result,
+    };
+
+    broadcast_logs(&data.uuid, &logs).await;
+    broadcast_attendance(&data.uuid, &sync).await;
+
+    let error = outcome_error(&outcome);
+    let result = CheckinResult { id, outcome };
+    match error {
+        Some((code, message)) => reply(ack, AckResponse::<()>::error(code, message)),
+        None => reply(ack, AckResponse::ok(result)),
+    }
+}
+
+// IDを1件だけ登録する（名簿にないIDは当日参加として登録する）
+async fn register_today(socket: SocketRef, TryData(data): TryData<TodayData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("register_today");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_lengths([&data.id], MAX_ID_LENGTH);
+    if let Err(response) = guard(&socket, &data.uuid, "register_today", validation).await {
+        return reply(ack, response);
+    }
+    info!("Received register_today from {} for {}: {}", socket.id, data.uuid, redact_id(&data.id));
+    let client = client_ip(&socket).unwrap_or_else(|| socket.id.to_string());
+
+    let result = get_event_store().update(&data.uuid, |state| {
+        if state.is_device_locked(&socket.id.to_string()) {
+            return Err((ErrorCode::DeviceLocked, DEVICE_LOCKED_MESSAGE.to_string()));
+        }
+        let version = state.version;
+        let id = state.resolve_card(&data.id);
+        // 再入室も新たな出席登録として扱う
+        let checking_in = if state.registered_at(&id).is_none() || state.is_checked_out(&id) {
+            vec![id.clone()]
+        } else {
+            Vec::new()
+        };
+        if let Err(reason) = check_qr_checkin(state, &socket.id.to_string(), &client, data.qr_token.as_deref(), &checking_in) {
+            warn!("Rejected register_today for {}: {}", data.uuid, reason);
+            return Err((ErrorCode::InvalidQrToken, reason));
+        }
+        let outcome = state.check_in_at(&id, chrono::Local::now().fixed_offset(), false);
+        let registered_count = usize::from(matches!(outcome, CheckinOutcome::Registered { .. }));
+        state.record_device_activity(&socket.id.to_string(), registered_count);
+        get_metrics().record_checkins(&data.uuid, registered_count);
+
+        let mut logs = Vec::new();
+        if matches!(outcome, CheckinOutcome::Registered { .. }) {
+            let message = if state.event.participants.contains(&id) {
+                format!("出席登録: {} が出席しました", id)
+            } else {
+                format!("当日参加登録: {} が参加しました", id)
+            };
+            logs.push(state.push_log("info", message));
+        } else if matches!(outcome, CheckinOutcome::LimitReached { .. }) {
+            let message = format!("当日参加者の上限に達したため {} を登録できませんでした", id);
+            logs.push(state.push_log("warning", message));
+        }
+        Ok((id, outcome, logs, state.sync_since(version)))
+    });
+
+    let Some(result) = result else {
+        warn!("No data found for event: {}", data.uuid);
+        reply(ack, AckResponse::event_not_found());
+        return;
+    };
+    let (id, outcome, logs, sync) = match result {
+        Ok(result) => result,
+        Err((code, message)) => {
+            reply(ack, AckResponse::<()>::error(code, message));
+            return;
+        }
+    };
+
+    let error = outcome_error(&outcome);
+    let result = CheckinResult { id, outcome };
+    match error {
+        Some((code, message)) => reply(ack, AckResponse::error(code, message).with_data(result)),
+        None => reply(ack, AckResponse::ok(result)),
     }
-}
 
-async fn broadcast_log(socket: &SocketRef, room: &str, level: &str, message: String) {
-    let log_entry = create_log_entry(level, message);
-    if let Err(e) = socket.within(room.to_string()).emit("activity_log", &log_entry).await {
-        eprintln!("Failed to broadcast log: {}", e);
-    }
+    send_logs(&socket, &data.uuid, &logs).await;
+    broadcast_attendance(&data.uuid, &sync).await;
 }
 
 
 
+// 送信元の端末に参加者IDごとの登録結果を返す
+fn send_checkin_results(socket: &SocketRef, results: &[CheckinResult]) {
+    if results.is_empty() {
+        return;
+    }
+    if let Err(e) = socket.emit("checkin_result", results) {
+        error!("Failed to send check-in results: {}", e);
+    }
+}
 
+fn rejected_results(ids: &[String], reason: &str) -> Vec<CheckinResult> {
+    ids.iter()
+        .map(|id| CheckinResult {
+            id: id.clone(),
+            outcome: CheckinOutcome::Rejected { reason: reason.to_string() },
+        })
+        .collect()
+}
 
-async fn on_connect(socket: SocketRef, Data(data): Data<String> ) {
-    println!("Client connected: {}", socket.id);
-    let app_state = get_app_state();
-    let return_data = app_state.get(&(data + ":datas"));
+fn registered(state: &EventState, id: &str) -> CheckinOutcome {
+    let arrival = state.current_session().records.get(id).map(|record| record.arrival).unwrap_or_default();
+    CheckinOutcome::Registered { arrival }
+}
 
-    if let Err(e) = socket.emit("debug_init_data", &return_data) {
-        eprintln!("Failed to send initial data: {}", e);
+fn already_registered(state: &EventState, id: &str) -> CheckinOutcome {
+    CheckinOutcome::AlreadyRegistered {
+        at: state.registered_at(id),
     }
 }
 
-async fn on_disconnect(socket: SocketRef) {
-    println!("Client disconnected: {}", socket.id);
+// 登録処理の結果（ロックの外で送信する）
+enum RegisterResult {
+    Rejected(ErrorCode, String),
+    Applied {
+        results: Vec<CheckinResult>,
+        logs: Vec<LogEntry>,
+        sync: AttendanceSync,
+    },
 }
 
-async fn on_new_message(socket: SocketRef, Data(data): Data<String>) {
-    println!("Received message from {}: {}", socket.id, data);
-    
-    // 他のクライアントにメッセージをブロードキャスト
-    if let Err(e) = socket.broadcast().emit("debug_new_msg", &data).await {
-        eprintln!("Failed to broadcast message: {}", e);
-    }
+#[derive(Deserialize, Serialize, Debug)]
+struct AttendeeData {
+    attendeeindex: Vec<i32>,
+    uuid: String,
+    // 端末で読み取ったID（指定があればこのIDの結果を返す）
+    #[serde(default)]
+    scanned: Option<Vec<String>>,
+    // モニタのQRコードから読み取ったトークン（QRコードによる出席登録が有効なイベントのみ）
+    #[serde(default)]
+    qr_token: Option<String>,
 }
 
-async fn join_data(socket: SocketRef, Data(data): Data<String>) {
-    println!("Client connected: {}", socket.id);
-    println!("Data received for join: {:?}", data);
-    println!("Data type: {}", std::any::type_name_of_val(&data));
-    println!("Data length: {}", data.len());
-    
-    if data.is_empty() || data == "undefined" || data == "null" {
-        eprintln!("Invalid UUID received: {}", data);
-        if let Err(e) = socket.emit("join_error", "無効なUUIDです") {
-            eprintln!("Failed to send error message: {}", e);
-        }
-        return;
+async fn register_attendees(socket: SocketRef, TryData(data): TryData<AttendeeData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("register_attendees");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_list_length(data.attendeeindex.len())
+        .and_then(|_| data.scanned.as_ref().map_or(Ok(()), |scanned| validate_ids(scanned.iter(), MAX_IDS_PER_REQUEST)));
+    if let Err(response) = guard(&socket, &data.uuid, "register_attendees", validation).await {
+        return reply(ack, response);
     }
-    
-    let app_state = get_app_state();
-    
-    let key = data.clone() + ":datas";
-    let return_data = app_state.get(&key);
-    if return_data.is_none() {
-        eprintln!("No data found for key: {}", key);
-        // UUIDが存在しない場合、エラーを返す
-        if let Err(e) = socket.emit("join_error", "指定されたイベントが見つかりません") {
-            eprintln!("Failed to send error message: {}", e);
+    info!("Received register_attendees from {} for {}: {}", socket.id, data.uuid, redact_ids(&data.attendeeindex));
+    let client = client_ip(&socket).unwrap_or_else(|| socket.id.to_string());
+
+    // 出席者の追加・入室記録・ログの保存を1回の更新で行う
+    let result = get_event_store().update(&data.uuid, |state| {
+        if state.is_device_locked(&socket.id.to_string()) {
+            emit_current_lists(&socket, state);
+            return RegisterResult::Rejected(ErrorCode::DeviceLocked, DEVICE_LOCKED_MESSAGE.to_string());
+        }
+        if let Some(reason) = reject_outside_window(&socket, state) {
+            return RegisterResult::Rejected(ErrorCode::OutsideWindow, reason);
         }
-        return;
-    }
-    let return_data = return_data.unwrap();
 
-    println!("Returning data: {:?}", return_data);
+        let version = state.version;
+        let existing_attendees = state.current_session().attendees;
+        // 再入室も新たな出席登録として扱う
+        let pending: Vec<String> = data
+            .attendeeindex
+            .iter()
+            .filter_map(|&index| usize::try_from(index).ok().and_then(|i| state.event.participants.get(i)).map(|id| (index, id)))
+            .filter(|(index, id)| !existing_attendees.contains(index) || state.is_checked_out(id))
+            .map(|(_, id)| id.clone())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        if let Err(reason) = check_qr_checkin(state, &socket.id.to_string(), &client, data.qr_token.as_deref(), &pending) {
+            warn!("Rejected register_attendees from {} for {}: {}", socket.id, data.uuid, reason);
+            emit_current_lists(&socket, state);
+            return RegisterResult::Rejected(ErrorCode::InvalidQrToken, reason);
+        }
+        let (new_ids, unknown) = state.register_attendees(&data.attendeeindex);
+        state.record_device_activity(&socket.id.to_string(), new_ids.len());
+        get_metrics().record_checkins(&data.uuid, new_ids.len());
+
+        // 送信元に参加者IDごとの結果を返す
+        let results: Vec<CheckinResult> = match &data.scanned {
+            Some(scanned) => scanned
+                .iter()
+                .map(|id| {
+                    let outcome = if new_ids.contains(id) {
+                        registered(state, id)
+                    } else if state.event.participants.iter().position(|participant| participant == id)
+                        .is_some_and(|index| existing_attendees.contains(&(index as i32)))
+                    {
+                        already_registered(state, id)
+                    } else {
+                        CheckinOutcome::UnknownId
+                    };
+                    CheckinResult { id: id.clone(), outcome }
+                })
+                .collect(),
+            None => new_ids
+                .iter()
+                .map(|id| CheckinResult { id: id.clone(), outcome: registered(state, id) })
+                .chain(unknown.iter().map(|index| CheckinResult {
+                    id: index.to_string(),
+                    outcome: CheckinOutcome::UnknownId,
+                }))
+                .collect(),
+        };
+
+        // 新規登録された出席者のログを保存
+        let mut logs: Vec<LogEntry> = new_ids
+            .iter()
+            .map(|student_id| state.push_log("info", format!("出席登録: {} が出席しました", student_id)))
+            .collect();
+        logs.extend(unknown.iter().map(|index| {
+            state.push_log("warning", format!("出席登録: インデックス {} (参加者情報が見つかりません)", index))
+        }));
+
+        RegisterResult::Applied {
+            results,
+            logs,
+            sync: state.sync_since(version),
+        }
+    });
 
-    // UUIDをroomとして使用してソケットを参加させる
-    let room_name = data.clone();
-    socket.join(room_name.clone());
-    println!("Socket {} joined room: {}", socket.id, room_name);
+    let Some(result) = result else {
+        warn!("No data found for event: {}", data.uuid);
+        reply(ack, AckResponse::event_not_found());
+        return;
+    };
 
-    // 保存された設定があれば取得し、イベントデータに反映
-    let app_state_settings = get_app_state4();
-    let settings_key = data.clone() + ":settings";
-    
-    let mut final_data = return_data.clone();
-    if let Some(saved_settings) = app_state_settings.get(&settings_key) {
-        println!("Found saved settings for {}: {:?}", data, saved_settings);
-        // 保存された設定で上書き
-        final_data.arrowtoday = saved_settings.arrowtoday;
-        final_data.autotodayregister = saved_settings.autotodayregister;
-        final_data.soukai = saved_settings.soukai;
-        final_data.nolist = saved_settings.nolist;
-    } else {
-        println!("No saved settings found for {}, using default settings from event data", data);
-    }
+    let (results, logs, sync) = match result {
+        RegisterResult::Rejected(code, reason) => {
+            let results = rejected_results(data.scanned.as_deref().unwrap_or_default(), &reason);
+            send_checkin_results(&socket, &results);
+            if code == ErrorCode::InvalidQrToken {
+                let message = format!("{}（{}）", reason, socket_label(&socket, &data.uuid));
+                broadcast_server_log(&data.uuid, "warning", message).await;
+            }
+            reply(ack, AckResponse::error(code, reason).with_data(results));
+            return;
+        }
+        RegisterResult::Applied { results, logs, sync } => (results, logs, sync),
+    };
 
-    // クライアント接続のログをブロードキャスト
-    let socket_clone = socket.clone();
-    let room_clone = room_name.clone();
-    tokio::spawn(async move {
-        broadcast_log(&socket_clone, &room_clone, "server", format!("クライアントが接続しました (ID: {})", &socket_clone.id.to_string()[..8])).await;
-    });
+    send_checkin_results(&socket, &results);
+    reply(ack, AckResponse::ok(results));
+    send_logs(&socket, &data.uuid, &logs).await;
 
-    // 最新の設定を反映したデータをクライアントに送信
-    if let Err(e) = socket.emit("join_return", &final_data) {
-        eprintln!("Failed to send initial data: {}", e);
-    }
+    // 追加された参加者の差分を同じroomのクライアントにのみブロードキャスト
+    broadcast_attendance(&data.uuid, &sync).await;
 }
 
-async fn sync_all_data(socket: SocketRef, Data(data): Data<String>) {
-    println!("Received sync_all_data from {}: {:?}", socket.id, data);
-    
-    if data.is_empty() || data == "undefined" || data == "null" {
-        eprintln!("Invalid UUID received in sync_all_data: {}", data);
-        return;
-    }
-    
-    // ここで全データを同期するロジックを実装
-    let app_state = get_app_state2();
-    let key = data.clone() + ":attendees";
-    let return_data = app_state.get(&key);
-
-    let app_state2 = get_app_state3();
-    let key2 = data.clone() + ":ontheday";
-    let return_data2 = app_state2.get(&key2);
-    println!("Returning data: {:?}, {:?}", return_data, return_data2);
+#[derive(Deserialize, Serialize, Debug)]
+struct OnTheDayData {
+    ontheday: Vec<String>,
+    uuid: String,
+    // 端末で読み取ったID（指定があればこのIDの結果を返す）
+    #[serde(default)]
+    scanned: Option<Vec<String>>,
+    // モニタのQRコードから読み取ったトークン（QRコードによる出席登録が有効なイベントのみ）
+    #[serde(default)]
+    qr_token: Option<String>,
+}
 
-    if let Err(e) = socket.emit("register_attendees_return", &(return_data)) {
-        eprintln!("Failed to send sync_all_data: {}", e);
+async fn register_ontheday(socket: SocketRef, TryData(data): TryData<OnTheDayData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("register_ontheday");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_ids(data.ontheday.iter(), MAX_IDS_PER_REQUEST)
+        .and_then(|_| data.scanned.as_ref().map_or(Ok(()), |scanned| validate_ids(scanned.iter(), MAX_IDS_PER_REQUEST)));
+    if let Err(response) = guard(&socket, &data.uuid, "register_ontheday", validation).await {
+        return reply(ack, response);
     }
+    info!("Received register_ontheday from {} for {}: {}", socket.id, data.uuid, redact_ids(&data.ontheday));
+    let client = client_ip(&socket).unwrap_or_else(|| socket.id.to_string());
+
+    let result = get_event_store().update(&data.uuid, |state| {
+        let version = state.version;
+        let existing_ontheday = state.current_session().ontheday;
+        // カード番号は参加者IDに置き換え、名簿の参加者は当日参加者として数えない
+        let ontheday: Vec<String> = data.ontheday.iter().map(|id| state.resolve_card(id)).collect();
+        let (roster, ontheday): (Vec<String>, Vec<String>) =
+            ontheday.into_iter().partition(|id| state.event.participants.contains(id));
+        let has_new = ontheday.iter().any(|id| !existing_ontheday.contains(id));
+
+        // 受付時間外、または当日参加が許可されていない場合は拒否
+        if state.is_device_locked(&socket.id.to_string()) {
+            emit_current_lists(&socket, state);
+            return RegisterResult::Rejected(ErrorCode::DeviceLocked, DEVICE_LOCKED_MESSAGE.to_string());
+        }
+        if let Some(reason) = reject_outside_window(&socket, state) {
+            return RegisterResult::Rejected(ErrorCode::OutsideWindow, reason);
+        }
+        if has_new && !state.settings().arrowtoday {
+            let reason = "当日参加は許可されていません";
+            warn!("Rejected ontheday registration for {}: {}", data.uuid, reason);
+            emit_current_lists(&socket, state);
+            return RegisterResult::Rejected(ErrorCode::OnthedayNotAllowed, reason.to_string());
+        }
+        let pending: Vec<String> = ontheday
+            .iter()
+            .filter(|id| !existing_ontheday.contains(id))
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let limit = state.max_ontheday();
+        if !pending.is_empty() && existing_ontheday.len() + pending.len() > limit {
+            warn!("Rejected ontheday registration for {}: limit {} reached", data.uuid, limit);
+            emit_current_lists(&socket, state);
+            return RegisterResult::Rejected(
+                ErrorCode::OnthedayLimitReached,
+                format!("当日参加者の上限（{}人）に達しました", limit),
+            );
+        }
+        // 再入室も新たな出席登録として扱う
+        let checking_in: Vec<String> = pending
+            .iter()
+            .chain(roster.iter().filter(|id| state.registered_at(id).is_none()))
+            .chain(roster.iter().chain(&ontheday).filter(|id| state.is_checked_out(id)))
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        if let Err(reason) = check_qr_checkin(state, &socket.id.to_string(), &client, data.qr_token.as_deref(), &checking_in) {
+            warn!("Rejected ontheday registration for {}: {}", data.uuid, reason);
+            emit_current_lists(&socket, state);
+            return RegisterResult::Rejected(ErrorCode::InvalidQrToken, reason);
+        }
 
-    if let Err(e) = socket.emit("register_ontheday_return", &(return_data2)) {
-        eprintln!("Failed to send sync_all_data: {}", e);
-    }
-}
+        let new_participants = state.register_ontheday(&data.ontheday);
+        state.record_device_activity(&socket.id.to_string(), new_participants.len());
+        get_metrics().record_checkins(&data.uuid, new_participants.len());
+        let reported: Vec<String> = data
+            .scanned
+            .as_ref()
+            .map(|scanned| scanned.iter().map(|id| state.resolve_card(id)).collect())
+            .unwrap_or_else(|| new_participants.clone());
+
+        // 送信元に参加者IDごとの結果を返す
+        let results: Vec<CheckinResult> = reported
+            .iter()
+            .map(|id| {
+                let outcome = if new_participants.contains(id) {
+                    registered(state, id)
+                } else if existing_ontheday.contains(id) || roster.contains(id) {
+                    already_registered(state, id)
+                } else {
+                    CheckinOutcome::UnknownId
+                };
+                CheckinResult { id: id.clone(), outcome }
+            })
+            .collect();
+
+        // 新規登録された参加者のみログを保存
+        let logs: Vec<LogEntry> = new_participants
+            .iter()
+            .map(|student_id| {
+                let message = if roster.contains(student_id) {
+                    format!("出席登録: {} が出席しました", student_id)
+                } else {
+                    format!("当日参加登録: {} が参加しました", student_id)
+                };
+                state.push_log("info", message)
+            })
+            .collect();
+
+        RegisterResult::Applied {
+            results,
+            logs,
+            sync: state.sync_since(version),
+        }
+    });
 
+    let Some(result) = result else {
+        warn!("No data found for event: {}", data.uuid);
+        reply(ack, AckResponse::event_not_found());
+        return;
+    };
 
+    let (results, logs, sync) = match result {
+        RegisterResult::Rejected(code, reason) => {
+            let reported = data.scanned.clone().unwrap_or_else(|| data.ontheday.clone());
+            let results = rejected_results(&reported, &reason);
+            send_checkin_results(&socket, &results);
+            if matches!(code, ErrorCode::OnthedayLimitReached | ErrorCode::InvalidQrToken) {
+                let message = format!("{}（{}）", reason, socket_label(&socket, &data.uuid));
+                broadcast_server_log(&data.uuid, "warning", message).await;
+            }
+            reply(ack, AckResponse::error(code, reason).with_data(results));
+            return;
+        }
+        RegisterResult::Applied { results, logs, sync } => (results, logs, sync),
+    };
 
+    send_checkin_results(&socket, &results);
+    reply(ack, AckResponse::ok(results));
+    send_logs(&socket, &data.uuid, &logs).await;
 
-async fn register_today(socket: SocketRef, Data(data): Data<String>) {
-    println!("Received register_today from {}: {}", socket.id, data);
-    
+    // 追加された当日参加者の差分を同じroomのクライアントにのみブロードキャスト
+    broadcast_attendance(&data.uuid, &sync).await;
 }
 
-
-
 #[derive(Deserialize, Serialize, Debug)]
-struct AttendeeData {
-    attendeeindex: Vec<i32>,
+struct CheckInData {
     uuid: String,
+    ops: Vec<CheckinOp>,
 }
 
-async fn register_attendees(socket: SocketRef, Data(data): Data<AttendeeData>) {
-    println!("Received register_attendees from {}: {:?}", socket.id, data);
-
-    let app_state = get_app_state2();
-    let key = data.uuid.clone() + ":attendees";
-
-    // 既存の出席者リストを取得
-    let existing_attendees = app_state.get(&key).unwrap_or_default();
-
-    // 新規登録者のみを抽出
-    let new_registrations: Vec<i32> = data.attendeeindex
-        .iter()
-        .filter(|&index| !existing_attendees.contains(index))
-        .copied()
-        .collect();
+// 操作ID付きの出席登録。再送された操作は反映せず、前回と同じ結果を返す
+async fn check_in(socket: SocketRef, TryData(data): TryData<CheckInData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("check_in");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_ids(data.ops.iter().map(|op| &op.id), MAX_IDS_PER_REQUEST)
+        .and_then(|_| validate_lengths(data.ops.iter().map(|op| &op.op_id), MAX_ID_LENGTH))
+        .and_then(|_| validate_lengths(data.ops.iter().filter_map(|op| op.client_timestamp.as_ref()), MAX_ID_LENGTH));
+    if let Err(response) = guard(&socket, &data.uuid, "check_in", validation).await {
+        return reply(ack, response);
+    }
+    info!("Received check_in from {} for {}: {} ops", socket.id, data.uuid, data.ops.len());
 
-    // result と data.attendeeindex をかぶりなしでマージ
-    let merged_attendees: Vec<i32> = match app_state.get(&key) {
-        Some(existing) => {
-            let mut combined = existing.clone();
-            for &item in &data.attendeeindex {
-                if !combined.contains(&item) {
-                    combined.push(item);
+    let result = get_event_store().update(&data.uuid, |state| {
+        // 一時停止中は反映しない（端末のキューに残り、解除後に再送される）
+        if state.is_device_locked(&socket.id.to_string()) {
+            return Err((ErrorCode::DeviceLocked, DEVICE_LOCKED_MESSAGE.to_string()));
+        }
+        // QRコードによる出席登録が有効な間は、トークンを送信できる register_attendees / register_ontheday / register_today のみ受け付ける
+        if requires_qr_token(state, &socket.id.to_string()) {
+            return Err((ErrorCode::InvalidQrToken, QR_TOKEN_REQUIRED.to_string()));
+        }
+        let version = state.version;
+        let mut logs = Vec::new();
+        let mut registered_count = 0;
+        let acks: Vec<OpAck> = data
+            .ops
+            .iter()
+            .map(|op| {
+                let op_ack = state.apply_op(op);
+                if op_ack.duplicate {
+                    return op_ack;
                 }
-            }
-            combined
-        },
-        None => data.attendeeindex.clone(),
-    };
-    // merged_attendees を 昇順にソート
-    let mut sorted_attendees = merged_attendees.clone();
-    sorted_attendees.sort_unstable();
-
-    println!("Merged attendees data: {:?}", sorted_attendees);
-
-    app_state.insert(key.clone(), sorted_attendees.clone());
-
-    // 新規登録された出席者のログを出力(インデックスから学籍番号を取得)
-    if !new_registrations.is_empty() {
-        let socket_clone = socket.clone();
-        let uuid_clone = data.uuid.clone();
-        tokio::spawn(async move {
-            // 参加者リストを取得
-            let app_state_participants = get_app_state();
-            let participants_key = uuid_clone.clone() + ":datas";
-            
-            if let Some(event_data) = app_state_participants.get(&participants_key) {
-                let participants = &event_data.participants;
-                for &index in &new_registrations {
-                    if let Some(student_id) = participants.get(index as usize) {
-                        broadcast_log(&socket_clone, &uuid_clone, "info", format!("出席登録: {} が出席しました", student_id)).await;
-                    } else {
-                        broadcast_log(&socket_clone, &uuid_clone, "warning", format!("出席登録: インデックス {} (参加者情報が見つかりません)", index)).await;
+                match op_ack.result.outcome {
+                    CheckinOutcome::Registered { .. } => {
+                        let suffix = if op.offline { "（オフライン）" } else { "" };
+                        let id = &op_ack.result.id;
+                        let message = if state.event.participants.contains(id) {
+                            format!("出席登録: {} が出席しました{}", id, suffix)
+                        } else {
+                            format!("当日参加登録: {} が参加しました{}", id, suffix)
+                        };
+                        logs.push(state.push_log("info", message));
+                        registered_count += 1;
+                    }
+                    CheckinOutcome::LimitReached { .. } => {
+                        let message =
+                            format!("当日参加者の上限に達したため {} を登録できませんでした", op_ack.result.id);
+                        logs.push(state.push_log("warning", message));
                     }
+                    _ => {}
                 }
-            }
-        });
-    }
+                op_ack
+            })
+            .collect();
 
-    // 参加者の情報を同じroomのクライアントにのみブロードキャスト
-    let room_name = data.uuid.clone();
-    if let Err(e) = socket.within(room_name.clone()).emit("register_attendees_return", &sorted_attendees).await {
-        eprintln!("Failed to send attendees data to room {}: {}", room_name, e);
+        state.record_device_activity(&socket.id.to_string(), registered_count);
+        get_metrics().record_checkins(&data.uuid, registered_count);
+        Ok((acks, logs, state.sync_since(version)))
+    });
+
+    let Some(result) = result else {
+        warn!("No data found for event: {}", data.uuid);
+        if let Err(e) = socket.emit("checkin_rejected", EVENT_NOT_FOUND) {
+            error!("Failed to send error message: {}", e);
+        }
+        reply(ack, AckResponse::event_not_found());
+        return;
+    };
+    let (acks, logs, sync) = match result {
+        Ok(result) => result,
+        Err((code, message)) => {
+            reply(ack, AckResponse::<()>::error(code, message));
+            return;
+        }
+    };
+
+    reply(ack, AckResponse::ok(acks));
+
+    // 新たに反映された操作がなければ送信しない
+    if logs.is_empty() {
+        return;
     }
+    send_logs(&socket, &data.uuid, &logs).await;
+    broadcast_attendance(&data.uuid, &sync).await;
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-struct OnTheDayData {
-    ontheday: Vec<String>,
+struct RemoveAttendanceData {
     uuid: String,
+    ids: Vec<String>,
 }
 
-async fn register_ontheday(socket: SocketRef, Data(data): Data<OnTheDayData>) {
-    println!("Received register_ontheday from {}: {:?}", socket.id, data.ontheday);
-    
-    let app_state = get_app_state3();
-    let key = data.uuid.clone() + ":ontheday";
-
-    // 既存の当日参加者リストを取得
-    let existing_ontheday = app_state.get(&key).unwrap_or_default();
-
-    // 新規登録者のみを抽出
-    let new_participants: Vec<String> = data.ontheday
-        .iter()
-        .filter(|id| !existing_ontheday.contains(id))
-        .cloned()
-        .collect();
-
-    // result と data.ontheday をかぶりなしでマージ
-    let merged_ontheday: Vec<String> = match app_state.get(&key) {
-        Some(existing) => {
-            let mut combined = existing.clone();
-            for item in &data.ontheday {
-                if !combined.contains(item) {
-                    combined.push(item.clone());
-                }
-            }
-            combined
-        },
-        None => data.ontheday.clone(),
+// 誤って登録した出席を取り消す
+async fn remove_attendance(socket: SocketRef, TryData(data): TryData<RemoveAttendanceData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("remove_attendance");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
     };
+    let validation = validate_ids(data.ids.iter(), MAX_IDS_PER_REQUEST);
+    if let Err(response) = guard(&socket, &data.uuid, "remove_attendance", validation).await {
+        return reply(ack, response);
+    }
+    info!("Received remove_attendance from {} for {}: {}", socket.id, data.uuid, redact_ids(&data.ids));
+    if let Err(response) = require_monitor(&socket, &data.uuid) {
+        return reply(ack, response);
+    }
 
+    let result = get_event_store().update(&data.uuid, |state| {
+        let version = state.version;
+        let removed = state.remove_attendance(&data.ids);
+        state.record_device_activity(&socket.id.to_string(), 0);
+        let logs: Vec<LogEntry> = removed
+            .iter()
+            .map(|student_id| state.push_log("info", format!("出席取消: {} の出席を取り消しました", student_id)))
+            .collect();
+        (removed, logs, state.sync_since(version))
+    });
 
-    app_state.insert(key.clone(), merged_ontheday.clone());
+    let Some((removed, logs, sync)) = result else {
+        warn!("No data found for event: {}", data.uuid);
+        if let Err(e) = socket.emit("remove_attendance_error", EVENT_NOT_FOUND) {
+            error!("Failed to send error message: {}", e);
+        }
+        reply(ack, AckResponse::event_not_found());
+        return;
+    };
 
-    // 新規登録された当日参加者のみログを出力
-    if !new_participants.is_empty() {
-        let socket_clone = socket.clone();
-        let uuid_clone = data.uuid.clone();
-        tokio::spawn(async move {
-            for student_id in &new_participants {
-                broadcast_log(&socket_clone, &uuid_clone, "info", format!("当日参加登録: {} が参加しました", student_id)).await;
-            }
-        });
-    }
+    reply(ack, AckResponse::ok(removed));
 
-    // 参加者の情報を同じroomのクライアントにのみブロードキャスト
-    let room_name = data.uuid.clone();
-    if let Err(e) = socket.within(room_name.clone()).emit("register_ontheday_return", &merged_ontheday).await {
-        eprintln!("Failed to send ontheday data to room {}: {}", room_name, e);
-    }
+    send_logs(&socket, &data.uuid, &logs).await;
+    broadcast_attendance(&data.uuid, &sync).await;
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -296,74 +1708,342 @@ struct SettingsData {
     autotodayregister: bool,
     soukai: bool,
     nolist: bool,
+    #[serde(default)]
+    requirecheckout: bool,
     uuid: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
-struct SettingsChangeData{
-    arrowtoday: bool,
-    autotodayregister: bool,
-}
-
 #[derive(Deserialize, Serialize, Debug)]
 struct UpdateSettingsData {
     uuid: String,
     settings: crate::Settings,
 }
 
-async fn update_settings(socket: SocketRef, Data(data): Data<UpdateSettingsData>) {
-    println!("Update settings from client: {:?}", data);
-
-    let app_state = get_app_state4();
-    let key = data.uuid.clone() + ":settings";
+async fn update_settings(socket: SocketRef, TryData(data): TryData<UpdateSettingsData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("update_settings");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    if let Err(response) = guard(&socket, &data.uuid, "update_settings", Ok(())).await {
+        return reply(ack, response);
+    }
+    info!("Update settings from client: {:?}", data);
+    if let Err(response) = require_monitor(&socket, &data.uuid) {
+        return reply(ack, response);
+    }
 
     // 設定をストレージに保存
-    app_state.insert(key.clone(), data.settings.clone());
+    if get_event_store().update(&data.uuid, |state| state.saved_settings = Some(data.settings.clone())).is_none() {
+        warn!("No data found for event: {}", data.uuid);
+        reply(ack, AckResponse::event_not_found());
+        return;
+    }
+    reply(ack, AckResponse::ok(data.settings.clone()));
 
     // 設定変更を同じroomの他のクライアントにブロードキャスト
     let room_name = data.uuid.clone();
     if let Err(e) = socket.within(room_name.clone()).emit("update_settings_return", &data.settings).await {
-        eprintln!("Failed to broadcast settings update to room {}: {}", room_name, e);
+        error!("Failed to broadcast settings update to room {}: {}", room_name, e);
+        get_metrics().record_broadcast_failure("update_settings_return");
     }
 }
 
-async fn settings_change(socket: SocketRef, Data(data): Data<SettingsData>) {
+async fn settings_change(socket: SocketRef, TryData(data): TryData<SettingsData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("settings_change");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    if let Err(response) = guard(&socket, &data.uuid, "settings_change", Ok(())).await {
+        return reply(ack, response);
+    }
     // ここに設定変更のロジックを実装
-    println!("Settings changed: {:?}", data);
-
-    let app_state = get_app_state4();
-    let key = data.uuid.clone() + ":settings";
+    info!("Settings changed: {:?}", data);
+    if let Err(response) = require_monitor(&socket, &data.uuid) {
+        return reply(ack, response);
+    }
 
     let return_data = crate::Settings {
         arrowtoday: data.arrowtoday,
         autotodayregister: data.autotodayregister,
         soukai: data.soukai,
         nolist: data.nolist,
+        requirecheckout: data.requirecheckout,
     };
 
-    app_state.insert(key.clone(), return_data.clone());
+    if get_event_store().update(&data.uuid, |state| state.saved_settings = Some(return_data.clone())).is_none() {
+        warn!("No data found for event: {}", data.uuid);
+        reply(ack, AckResponse::event_not_found());
+        return;
+    }
+    reply(ack, AckResponse::ok(return_data.clone()));
 
     // 設定変更を同じroomのクライアントにのみブロードキャスト
     let room_name = data.uuid.clone();
     if let Err(e) = socket.within(room_name.clone()).emit("settings_change_return", &return_data).await {
-        eprintln!("Failed to send settings change data to room {}: {}", room_name, e);
+        error!("Failed to send settings change data to room {}: {}", room_name, e);
+        get_metrics().record_broadcast_failure("settings_change_return");
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct SetActiveSessionData {
+    uuid: String,
+    session_id: String,
+}
+
+// アクティブなセッションの変更と、そのセッションの出席データをroomに送信
+pub async fn broadcast_active_session(uuid: &str, session: &crate::Session) {
+    let Some(io) = get_socket_io() else {
+        return;
+    };
+    let Some((attendance, arrivals, version)) = get_event_store().read(uuid, |state| {
+        (state.session(Some(&session.id)), state.arrival_categories(Some(&session.id)), state.version)
+    }) else {
+        return;
+    };
+
+    if let Err(e) = io.to(uuid.to_string()).emit("active_session_changed", session).await {
+        error!("Failed to send active session to room {}: {}", uuid, e);
+        get_metrics().record_broadcast_failure("active_session_changed");
+    }
+    if let Err(e) = io.to(uuid.to_string()).emit("register_attendees_return", &(attendance.attendees, arrivals)).await {
+        error!("Failed to send attendees data to room {}: {}", uuid, e);
+        get_metrics().record_broadcast_failure("register_attendees_return");
+    }
+    if let Err(e) = io.to(uuid.to_string()).emit("register_ontheday_return", &attendance.ontheday).await {
+        error!("Failed to send ontheday data to room {}: {}", uuid, e);
+        get_metrics().record_broadcast_failure("register_ontheday_return");
+    }
+    if let Err(e) = io.to(uuid.to_string()).emit("attendance_version", &version).await {
+        error!("Failed to send attendance version to room {}: {}", uuid, e);
+        get_metrics().record_broadcast_failure("attendance_version");
+    }
+}
+
+async fn set_active_session(socket: SocketRef, TryData(data): TryData<SetActiveSessionData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("set_active_session");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_lengths([&data.session_id], MAX_ID_LENGTH);
+    if let Err(response) = guard(&socket, &data.uuid, "set_active_session", validation).await {
+        return reply(ack, response);
+    }
+    info!("Received set_active_session from {}: {:?}", socket.id, data);
+    if let Err(response) = require_monitor(&socket, &data.uuid) {
+        return reply(ack, response);
+    }
+
+    // セッションの切り替えとログの保存を同時に行う
+    let result = get_event_store().try_update(&data.uuid, |state| {
+        let session = state.set_active_session(&data.session_id)?;
+        let log_entry = state.push_log("server", format!("セッションを切り替えました: {} {}", session.date, session.label));
+        Ok((session, log_entry))
+    });
+
+    match result {
+        Ok((session, log_entry)) => {
+            reply(ack, AckResponse::ok(session.clone()));
+            broadcast_active_session(&data.uuid, &session).await;
+            send_logs(&socket, &data.uuid, &[log_entry]).await;
+        }
+        Err(message) => {
+            warn!("Failed to set active session: {}", message);
+            if let Err(e) = socket.emit("set_active_session_error", &message) {
+                error!("Failed to send error message: {}", e);
+            }
+            let code = if message == EVENT_NOT_FOUND {
+                ErrorCode::EventNotFound
+            } else {
+                ErrorCode::SessionNotFound
+            };
+            reply(ack, AckResponse::<()>::error(code, message));
+        }
+    }
+}
+
+async fn arrival_statistics(socket: SocketRef, TryData(data): TryData<String>, ack: AckSender) {
+    let _timer = HandlerTimer::start("arrival_statistics");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    if let Err(response) = require_joined(&socket, &data) {
+        return reply(ack, response);
+    }
+    let statistics = get_event_store().read(&data, |state| state.arrival_statistics(state.active_session().as_deref()));
+    if let Err(e) = socket.emit("arrival_statistics_return", &statistics.clone().unwrap_or_default()) {
+        error!("Failed to send arrival statistics: {}", e);
+    }
+
+    match statistics {
+        Some(statistics) => reply(ack, AckResponse::ok(statistics)),
+        None => reply(ack, AckResponse::event_not_found()),
+    }
+}
+
+async fn attendance_totals(socket: SocketRef, TryData(data): TryData<String>, ack: AckSender) {
+    let _timer = HandlerTimer::start("attendance_totals");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    if let Err(response) = require_joined(&socket, &data) {
+        return reply(ack, response);
+    }
+    let totals = get_event_store().read(&data, |state| state.attendance_totals());
+    if let Err(e) = socket.emit("attendance_totals_return", &totals.clone().unwrap_or_default()) {
+        error!("Failed to send attendance totals: {}", e);
+    }
+
+    match totals {
+        Some(totals) => reply(ack, AckResponse::ok(totals)),
+        None => reply(ack, AckResponse::event_not_found()),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct CheckOutData {
+    uuid: String,
+    ids: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct CheckOutReturn {
+    ids: Vec<String>,
+    records: HashMap<String, crate::AttendanceRecord>,
+}
+
+// 退室を記録して動作ログに保存し、退室したIDとログを返す（Socket.IO・Tauri コマンド共通）
+pub fn record_check_out(state: &mut EventState, ids: &[String]) -> (Vec<String>, Vec<LogEntry>) {
+    let checked_out = state.check_out(ids);
+    let logs = checked_out
+        .iter()
+        .map(|student_id| state.push_log("info", format!("退室: {} が退室しました", student_id)))
+        .collect();
+    (checked_out, logs)
+}
+
+// 退室した参加者の記録をroomに送信
+pub async fn broadcast_check_out(uuid: &str, ids: &[String]) {
+    let Some(io) = get_socket_io() else {
+        return;
+    };
+    if ids.is_empty() {
+        return;
+    }
+
+    let records = get_event_store()
+        .read(uuid, |state| state.current_session().records)
+        .unwrap_or_default();
+    let return_data = CheckOutReturn {
+        ids: ids.to_vec(),
+        records: ids
+            .iter()
+            .filter_map(|id| records.get(id).map(|record| (id.clone(), record.clone())))
+            .collect(),
+    };
+
+    if let Err(e) = io.to(uuid.to_string()).emit("check_out_return", &return_data).await {
+        error!("Failed to send check out data to room {}: {}", uuid, e);
+        get_metrics().record_broadcast_failure("check_out_return");
+    }
+}
+
+async fn check_out(socket: SocketRef, TryData(data): TryData<CheckOutData>, ack: AckSender) {
+    let _timer = HandlerTimer::start("check_out");
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return reject_invalid(ack, e),
+    };
+    let validation = validate_ids(data.ids.iter(), MAX_IDS_PER_REQUEST);
+    if let Err(response) = guard(&socket, &data.uuid, "check_out", validation).await {
+        return reply(ack, response);
+    }
+    info!("Received check_out from {} for {}: {}", socket.id, data.uuid, redact_ids(&data.ids));
+
+    // 退室の記録とログの保存を同時に行う
+    let result = get_event_store().update(&data.uuid, |state| {
+        state.record_device_activity(&socket.id.to_string(), 0);
+        record_check_out(state, &data.ids)
+    });
+
+    let Some((checked_out, logs)) = result else {
+        warn!("Failed to check out: {}", EVENT_NOT_FOUND);
+        if let Err(e) = socket.emit("check_out_error", EVENT_NOT_FOUND) {
+            error!("Failed to send error message: {}", e);
+        }
+        reply(ack, AckResponse::event_not_found());
+        return;
+    };
+
+    // 在室していなかったIDは送信元に通知
+    let not_present: Vec<&String> = data.ids.iter().filter(|id| !checked_out.contains(id)).collect();
+    if not_present.is_empty() {
+        reply(ack, AckResponse::ok(checked_out.clone()));
+    } else {
+        let message = format!("在室していません: {}", not_present.iter().map(|id| id.as_str()).collect::<Vec<_>>().join(", "));
+        if let Err(e) = socket.emit("check_out_error", &message) {
+            error!("Failed to send error message: {}", e);
+        }
+        reply(ack, AckResponse::error(ErrorCode::NotPresent, message).with_data(checked_out.clone()));
     }
+
+    send_logs(&socket, &data.uuid, &logs).await;
+    broadcast_check_out(&data.uuid, &checked_out).await;
 }
 
+// Socket.IOサーバーで使用するポート
+pub const SOCKET_PORT: u16 = 50345;
 
 pub async fn start_socketio_server(port: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let listeners = bind_socketio_server(port).await?;
+    serve_socketio(listeners).await
+}
+
+// 静的ファイル配信用のHTTPサーバーを起動し、Socket.IOサーバーのポートを確保する
+// LANのアドレスに加えて 127.0.0.1 でも待ち受け、サーバーを起動したPC（アプリのモニタ）はループバックで接続する
+pub async fn bind_socketio_server(port: u16) -> Result<Vec<tokio::net::TcpListener>, Box<dyn std::error::Error + Send + Sync>> {
+    let my_domain = local_ip()?;
+
+    // 静的ファイル配信用のHTTPサーバーを別ポートで起動
+    let http_port = 50080;
+    tokio::spawn(async move {
+        if let Err(e) = start_http_server(http_port, port).await {
+            error!("Failed to start HTTP server: {}", e);
+        }
+    });
+
+    // Start the server
+    let mut listeners = vec![tokio::net::TcpListener::bind(format!("{}:{}", my_domain, port)).await?];
+    info!("Socket.IO server listening on {}:{}", my_domain, port);
+    if !my_domain.is_loopback() {
+        let loopback = IpAddr::from([127, 0, 0, 1]);
+        listeners.push(tokio::net::TcpListener::bind((loopback, port)).await?);
+        info!("Socket.IO server listening on {}:{}", loopback, port);
+    }
+    Ok(listeners)
+}
+
+// 指定したリスナーでSocket.IOサーバーを起動する（すべてのリスナーで同じサーバーを共有する）
+pub async fn serve_socketio(listeners: Vec<tokio::net::TcpListener>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let (layer, io) = SocketIo::new_layer();
 
     // 接続時のハンドラー
     io.ns("/", |s: SocketRef| {
-        println!("New connection: {}", s.id);
+        debug!("New connection: {}", s.id);
+
+        // 開発用のイベント（debug フィーチャー有効時のみ）
+        #[cfg(feature = "debug")]
+        {
+            s.on("new_message", on_new_message);
+            s.on("connect", on_connect);
+        }
 
-        //叙階接続時にはhashmapの内容を送信
-        
-        s.on("new_message", on_new_message);
-        s.on("disconnect", on_disconnect);
         s.on_disconnect(on_disconnect);
-        s.on("connect", on_connect);
         s.on("join", join_data);
         s.on("register_today" , register_today);
         s.on("register_attendees", register_attendees);
@@ -371,35 +2051,64 @@ pub async fn start_socketio_server(port: u16) -> Result<(), Box<dyn std::error::
         s.on("settings_change", settings_change);
         s.on("update_settings", update_settings);
         s.on("sync_all_data", sync_all_data);
+        s.on("set_active_session", set_active_session);
+        s.on("attendance_totals", attendance_totals);
+        s.on("arrival_statistics", arrival_statistics);
+        s.on("check_out", check_out);
+        s.on("check_in", check_in);
+        s.on("sync_since", sync_since);
+        s.on("remove_attendance", remove_attendance);
+        s.on("admin_kick", admin_kick);
+        s.on("admin_lock", admin_lock);
+        s.on("admin_ban", admin_ban);
+        s.on("announce", announce_event);
+        s.on("regenerate_join_code", regenerate_join_code);
+        s.on("self_check_in", self_check_in);
+        s.on("qr_check_in", qr_check_in);
+        s.on_fallback(on_unknown_event);
     });
 
-    let my_domain = local_ip().unwrap();
+    let _ = SOCKET_IO.set(io);
 
-    // 静的ファイル配信用のHTTPサーバーを別ポートで起動
-    let http_port = 50080;
-    tokio::spawn(async move {
-        if let Err(e) = start_http_server(http_port).await {
-            eprintln!("Failed to start HTTP server: {}", e);
-        }
-    });
+    // 受付時間・接続端末の監視タスク
+    tokio::spawn(watch_checkin_windows());
+    tokio::spawn(watch_devices());
+    tokio::spawn(watch_self_checkin_codes());
+    tokio::spawn(watch_qr_tokens());
+
+    // 負荷監視用の /metrics（環境変数で有効にした場合のみ）
+    let mut app = axum::Router::new();
+    if metrics_enabled() {
+        info!("Metrics endpoint enabled at /metrics");
+        app = app.route("/metrics", axum::routing::get(metrics_handler));
+    }
+    // 管理用のREST API（トークンを設定した場合のみ）
+    if admin_token().is_some() {
+        info!("Admin API enabled at /api");
+        app = app.merge(admin_api_router());
+    }
 
     // Create the app with CORS and Socket.IO layers
-    let app = axum::Router::new()
+    let app = app
         .layer(ServiceBuilder::new()
             .layer(CorsLayer::permissive())
             .layer(layer));
 
-    // Start the server
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", my_domain, port)).await?;
-    println!("Socket.IO server listening on {}:{}", my_domain, port);
-    
     *IS_SERVER_RUNNING.lock().unwrap() = true;
 
-    axum::serve(listener, app).await?;
+    // 端末一覧に接続元のIPアドレスを表示するため、接続情報を渡す
+    let mut servers = tokio::task::JoinSet::new();
+    for listener in listeners {
+        let app = app.clone();
+        servers.spawn(async move { axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await });
+    }
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
     Ok(())
 }
 
-async fn start_http_server(port: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn start_http_server(port: u16, socket_port: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let my_domain = local_ip().unwrap();
     
     // 静的ファイルのパスを取得
@@ -419,18 +2128,26 @@ async fn start_http_server(port: u16) -> Result<(), Box<dyn std::error::Error +
     
     // 静的ファイルが存在しない場合は作成
     if !static_dir.exists() {
-        println!("Warning: Static directory does not exist, creating: {}", static_dir.display());
+        warn!("Static directory does not exist, creating: {}", static_dir.display());
         std::fs::create_dir_all(&static_dir)?;
     }
 
     // 静的ファイル配信用のルーター
+    // /config.js でSocket.IOサーバーのポートを渡す（URLで server を指定しなかった場合に使う）
+    let config = format!("window.LIST_CHECKER_SOCKET_PORT = {};\n", socket_port);
     let app = axum::Router::new()
+        .route(
+            "/config.js",
+            axum::routing::get(move || async move {
+                ([(axum::http::header::CONTENT_TYPE, "application/javascript")], config)
+            }),
+        )
         .fallback_service(ServeDir::new(&static_dir))
         .layer(CorsLayer::permissive());
 
     let listener = tokio::net::TcpListener::bind(format!("{}:{}", my_domain, port)).await?;
-    println!("HTTP server listening on http://{}:{}", my_domain, port);
-    println!("Serving static files from: {}", static_dir.display());
+    info!("HTTP server listening on http://{}:{}", my_domain, port);
+    info!("Serving static files from: {}", static_dir.display());
     
     axum::serve(listener, app).await?;
     Ok(())
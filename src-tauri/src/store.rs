@@ -0,0 +1,1648 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use chrono::{DateTime, Duration, FixedOffset, Local};
+use uuid::Uuid;
+
+use crate::{
+    is_loopback, parse_local_datetime, token_matches, ArrivalCategory, ArrivalStatistics, AttendanceRecord, AttendanceTotal,
+    Eventstruct, Interval, Session, Settings, WindowState, QR_TOKEN_TTL_SECS,
+};
+
+pub const EVENT_NOT_FOUND: &str = "指定されたイベントが見つかりません";
+pub const DEVICE_NOT_FOUND: &str = "指定された端末が見つかりません";
+pub const DEVICE_TOKEN_NOT_FOUND: &str = "指定された端末トークンが見つかりません";
+pub const CANNOT_BAN_LOOPBACK: &str = "サーバーを起動したPCのIPアドレスは禁止できません";
+
+// 保存するログの上限（古いものから削除）
+const MAX_LOGS: usize = 1000;
+
+// 当日参加者の上限（イベントごとに変更できる）
+pub const DEFAULT_MAX_ONTHEDAY: usize = 1000;
+
+// 保持する出席データの差分の上限（これより遅れた端末には全体を送り直す）
+const MAX_CHANGES: usize = 500;
+
+// 端末のキューに残った操作として受け付ける古さの上限（これより前の時刻は上限の時刻に切り上げる）
+const MAX_OP_AGE_HOURS: i64 = 12;
+// 反映済みとして覚えておく操作IDの上限（古いものから削除）
+const MAX_PROCESSED_OPS: usize = 10000;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String,
+    pub message: String,
+}
+
+impl LogEntry {
+    pub fn new(level: &str, message: String) -> Self {
+        Self {
+            timestamp: Local::now().format("%H:%M:%S").to_string(),
+            level: level.to_string(),
+            message,
+        }
+    }
+}
+
+// 参加者IDごとの登録結果
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum CheckinOutcome {
+    Registered { arrival: ArrivalCategory },
+    AlreadyRegistered {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        at: Option<String>,
+    },
+    UnknownId,
+    Rejected { reason: String },
+    // 当日参加者の上限に達した
+    LimitReached { limit: usize },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CheckinResult {
+    pub id: String,
+    #[serde(flatten)]
+    pub outcome: CheckinOutcome,
+}
+
+// 端末が生成した操作ID付きの出席登録（オフライン中に溜めた分の再送にも使う）
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CheckinOp {
+    pub op_id: String,
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_timestamp: Option<String>,
+    #[serde(default)]
+    pub offline: bool,
+}
+
+// カード番号の対応表を読み込んだ結果
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CardImport {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+// 操作ごとの受領通知（端末はこれを受け取ったらキューから削除する）
+#[derive(Serialize, Debug, Clone)]
+pub struct OpAck {
+    pub op_id: String,
+    pub duplicate: bool,
+    #[serde(flatten)]
+    pub result: CheckinResult,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+}
+
+impl ChangeKind {
+    // 端末に送信するイベント名
+    pub fn event_name(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "attendance_added",
+            ChangeKind::Removed => "attendance_removed",
+        }
+    }
+}
+
+// 出席データの差分（変更のたびにバージョンが1つ進む）
+#[derive(Serialize, Debug, Clone)]
+pub struct AttendanceDelta {
+    pub version: u64,
+    pub kind: ChangeKind,
+    pub session: Option<String>,
+    // 名簿のインデックス
+    pub attendees: Vec<i32>,
+    pub ontheday: Vec<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub arrivals: HashMap<String, ArrivalCategory>,
+}
+
+// 差分で追いつけない端末に送る現在の出席データ
+#[derive(Serialize, Debug, Clone)]
+pub struct AttendanceSnapshot {
+    pub version: u64,
+    pub session: Option<String>,
+    pub attendees: Vec<i32>,
+    pub ontheday: Vec<String>,
+    pub arrivals: HashMap<String, ArrivalCategory>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AttendanceSync {
+    Deltas(Vec<AttendanceDelta>),
+    Snapshot(AttendanceSnapshot),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceRole {
+    Kiosk,
+    Monitor,
+    #[default]
+    Unknown,
+}
+
+// roomに接続した端末（切断後も一覧に残し、切断されたことが分かるようにする）
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceInfo {
+    pub socket_id: String,
+    pub role: DeviceRole,
+    pub device_name: String,
+    pub ip: Option<String>,
+    pub connected_since: String,
+    pub last_activity: String,
+    // この端末から登録された出席の件数
+    pub checkins: usize,
+    pub online: bool,
+    // 一時停止中の端末からの登録は受け付けない
+    pub locked: bool,
+    // 端末トークンで参加した場合はトークンのID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
+}
+
+// 端末ごとに発行する参加用のトークン（URLに埋め込んでキオスクに配布し、1台ずつ無効にできる）
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DeviceToken {
+    pub id: String,
+    pub token: String,
+    pub device_name: String,
+    pub role: DeviceRole,
+    pub created_at: String,
+    pub expires_at: String,
+}
+
+impl DeviceToken {
+    pub fn is_valid_at(&self, now: DateTime<FixedOffset>) -> bool {
+        DateTime::parse_from_rfc3339(&self.expires_at).is_ok_and(|expires_at| now < expires_at)
+    }
+}
+
+// セッション単位の出席データ（セッションのないイベントはキーが None）
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct SessionAttendance {
+    pub attendees: Vec<i32>,
+    pub ontheday: Vec<String>,
+    pub records: HashMap<String, AttendanceRecord>,
+}
+
+// ファイルに保存するイベントのデータ（接続端末や差分など、実行中にのみ必要なものは含めない）
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SavedEvent {
+    pub event: Eventstruct,
+    #[serde(default)]
+    pub saved_settings: Option<Settings>,
+    // JSONのキーに None は使えないため、セッションIDとの組で保存する
+    #[serde(default)]
+    pub attendance: Vec<(Option<String>, SessionAttendance)>,
+    #[serde(default)]
+    pub logs: Vec<LogEntry>,
+    #[serde(default)]
+    pub banned_ips: HashSet<String>,
+    #[serde(default)]
+    pub device_tokens: Vec<DeviceToken>,
+    #[serde(default)]
+    pub self_checkin_secret: Option<String>,
+    #[serde(default)]
+    pub qr_secret: Option<String>,
+    #[serde(default)]
+    pub card_ids: HashMap<String, String>,
+}
+
+// イベントごとの状態。出席・設定・ログをまとめて保持する
+#[derive(Debug, Clone)]
+pub struct EventState {
+    pub event: Eventstruct,
+    // 端末から保存された設定（未保存の場合はイベント作成時の設定を使う）
+    pub saved_settings: Option<Settings>,
+    pub attendance: HashMap<Option<String>, SessionAttendance>,
+    pub logs: Vec<LogEntry>,
+    // 反映済みの操作IDと結果（再送された操作を重複して反映しない）
+    pub processed_ops: HashMap<String, CheckinResult>,
+    // 反映した順の操作IDと反映した時刻（古い操作IDを削除するため）
+    pub processed_op_order: VecDeque<(DateTime<FixedOffset>, String)>,
+    // 出席データのバージョンと、直近の差分
+    pub version: u64,
+    pub changes: VecDeque<AttendanceDelta>,
+    // ソケットIDごとの接続端末と、前回の通知以降に変更があったか
+    pub devices: HashMap<String, DeviceInfo>,
+    pub devices_changed: bool,
+    // 接続を禁止したIPアドレス（イベントの終了まで有効）
+    pub banned_ips: HashSet<String>,
+    // トークンのIDごとの端末トークン
+    pub device_tokens: HashMap<String, DeviceToken>,
+    // セルフ出席登録のコードを求めるための秘密鍵
+    pub self_checkin_secret: String,
+    // セルフ出席登録・QRコードによる出席登録に使われたIPアドレスと登録したID（1台の端末で複数人を登録できないようにする）
+    pub self_checkins: HashMap<String, String>,
+    // QRコードのトークンに署名するための秘密鍵
+    pub qr_secret: String,
+    // 使用されたQRコードのトークン（nonce）ごとの発行時刻と、使用した端末
+    pub used_qr_tokens: HashMap<String, (u64, HashSet<String>)>,
+    // 学生証のバーコード・ICカードの番号と参加者IDの対応表
+    pub card_ids: HashMap<String, String>,
+}
+
+impl EventState {
+    pub fn new(event: Eventstruct) -> Self {
+        Self {
+            event,
+            saved_settings: None,
+            attendance: HashMap::new(),
+            logs: Vec::new(),
+            processed_ops: HashMap::new(),
+            processed_op_order: VecDeque::new(),
+            version: 0,
+            changes: VecDeque::new(),
+            devices: HashMap::new(),
+            devices_changed: false,
+            banned_ips: HashSet::new(),
+            device_tokens: HashMap::new(),
+            self_checkin_secret: Uuid::new_v4().simple().to_string(),
+            self_checkins: HashMap::new(),
+            qr_secret: Uuid::new_v4().simple().to_string(),
+            used_qr_tokens: HashMap::new(),
+            card_ids: HashMap::new(),
+        }
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.saved_settings.clone().unwrap_or(Settings {
+            arrowtoday: self.event.arrowtoday,
+            autotodayregister: self.event.autotodayregister,
+            soukai: self.event.soukai,
+            nolist: self.event.nolist,
+            requirecheckout: self.event.requirecheckout,
+        })
+    }
+
+    // 保存された設定を反映したイベントデータ
+    pub fn event_with_settings(&self) -> Eventstruct {
+        let settings = self.settings();
+        let mut event = self.event.clone();
+        event.arrowtoday = settings.arrowtoday;
+        event.autotodayregister = settings.autotodayregister;
+        event.soukai = settings.soukai;
+        event.nolist = settings.nolist;
+        event.requirecheckout = settings.requirecheckout;
+        event.card_reader = !self.card_ids.is_empty();
+        event
+    }
+
+    pub fn max_ontheday(&self) -> usize {
+        self.event.max_ontheday.unwrap_or(DEFAULT_MAX_ONTHEDAY)
+    }
+
+    pub fn active_session(&self) -> Option<String> {
+        self.event.active_session.clone()
+    }
+
+    pub fn session(&self, session: Option<&str>) -> SessionAttendance {
+        self.attendance
+            .get(&session.map(str::to_string))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn current_session(&self) -> SessionAttendance {
+        self.session(self.event.active_session.as_deref())
+    }
+
+    pub fn current_session_mut(&mut self) -> &mut SessionAttendance {
+        self.attendance.entry(self.event.active_session.clone()).or_default()
+    }
+
+    fn session_ids(&self) -> Vec<Option<String>> {
+        if self.event.sessions.is_empty() {
+            vec![None]
+        } else {
+            self.event.sessions.iter().map(|session| Some(session.id.clone())).collect()
+        }
+    }
+
+    // セッションを追加し、アクティブなセッションが変わったかを返す
+    // 最初のセッションはアクティブにし、セッションなしで登録済みの出席データはそのセッションに移す
+    pub fn add_session(&mut self, session: Session) -> bool {
+        let first = self.event.active_session.is_none();
+        if first {
+            if let Some(attendance) = self.attendance.remove(&None) {
+                self.attendance.insert(Some(session.id.clone()), attendance);
+            }
+            self.event.active_session = Some(session.id.clone());
+            self.reset_changes();
+        }
+        self.event.sessions.push(session);
+        first
+    }
+
+    pub fn set_active_session(&mut self, session_id: &str) -> Result<Session, String> {
+        let session = self
+            .event
+            .sessions
+            .iter()
+            .find(|session| session.id == session_id)
+            .cloned()
+            .ok_or_else(|| format!("セッション {} が見つかりません", session_id))?;
+
+        self.event.active_session = Some(session.id.clone());
+        self.reset_changes();
+        Ok(session)
+    }
+
+    // 受付時間の状態（受付時間が未設定なら常に受付中）
+    pub fn window_state(&self) -> WindowState {
+        self.event
+            .checkin_window
+            .as_ref()
+            .map(|window| window.state_at(Local::now().fixed_offset()))
+            .unwrap_or(WindowState::Open)
+    }
+
+    // 到着区分を判定（基準がなければ受付時間の遅刻扱い開始時刻を使う）
+    pub fn classify_arrival(&self, at: DateTime<FixedOffset>) -> ArrivalCategory {
+        if let Some(policy) = &self.event.arrival_policy {
+            return policy.classify(at);
+        }
+
+        if self.event.checkin_window.as_ref().is_some_and(|window| window.is_late(at)) {
+            ArrivalCategory::Late
+        } else {
+            ArrivalCategory::OnTime
+        }
+    }
+
+    // 名簿のインデックスで出席登録し、新規登録されたIDと名簿にないインデックスを返す
+    pub fn register_attendees(&mut self, indexes: &[i32]) -> (Vec<String>, Vec<i32>) {
+        let participants = &self.event.participants;
+        let (known, unknown): (Vec<i32>, Vec<i32>) = indexes
+            .iter()
+            .copied()
+            .partition(|&index| usize::try_from(index).is_ok_and(|i| i < participants.len()));
+
+        let session = self.attendance.entry(self.event.active_session.clone()).or_default();
+        let mut new_indexes = Vec::new();
+        let mut new_ids = Vec::new();
+        for index in known {
+            let id = &participants[index as usize];
+            if !session.attendees.contains(&index) {
+                session.attendees.push(index);
+                new_indexes.push(index);
+                new_ids.push(id.clone());
+            } else if session.records.get(id).is_some_and(AttendanceRecord::is_checked_out) {
+                // 退室済みの参加者は再入室として登録する
+                new_ids.push(id.clone());
+            }
+        }
+        session.attendees.sort_unstable();
+
+        self.record_check_ins(&new_ids);
+        self.push_change(ChangeKind::Added, new_indexes, Vec::new());
+        (new_ids, unknown)
+    }
+
+    // 当日参加者を登録し、新規登録されたIDを返す
+    // カード番号は参加者IDに置き換え、名簿の参加者であれば出席として登録する
+    pub fn register_ontheday(&mut self, ids: &[String]) -> Vec<String> {
+        let ids: Vec<String> = ids.iter().map(|id| self.resolve_card(id)).collect();
+        let indexes: Vec<i32> = ids
+            .iter()
+            .filter_map(|id| self.event.participants.iter().position(|participant| participant == id))
+            .map(|index| index as i32)
+            .collect();
+        let mut new_ids = if indexes.is_empty() { Vec::new() } else { self.register_attendees(&indexes).0 };
+        let first_ontheday = new_ids.len();
+
+        let participants = &self.event.participants;
+        let session = self.attendance.entry(self.event.active_session.clone()).or_default();
+        for id in ids.iter().filter(|id| !participants.contains(id)) {
+            if !session.ontheday.contains(id) {
+                session.ontheday.push(id.clone());
+                new_ids.push(id.clone());
+            } else if session.records.get(id).is_some_and(AttendanceRecord::is_checked_out) {
+                // 退室済みの参加者は再入室として登録する
+                new_ids.push(id.clone());
+            }
+        }
+
+        let ontheday = new_ids[first_ontheday..].to_vec();
+        self.record_check_ins(&ontheday);
+        self.push_change(ChangeKind::Added, Vec::new(), ontheday);
+        new_ids
+    }
+
+    // 新たに出席した参加者の入室を記録（退室済みの参加者は新しい区間を追加する）
+    fn record_check_ins(&mut self, ids: &[String]) {
+        self.record_check_ins_at(ids, Local::now().fixed_offset(), false);
+    }
+
+    fn record_check_ins_at(&mut self, ids: &[String], at: DateTime<FixedOffset>, offline: bool) {
+        if ids.is_empty() {
+            return;
+        }
+
+        let arrival = self.classify_arrival(at);
+        let at = at.to_rfc3339();
+
+        let records = &mut self.current_session_mut().records;
+        for id in ids {
+            match records.get_mut(id) {
+                Some(record) if record.is_checked_out() => record.intervals.push(Interval {
+                    check_in: at.clone(),
+                    check_out: None,
+                }),
+                Some(_) => {}
+                None => {
+                    records.insert(id.clone(), AttendanceRecord::checked_in(&at, arrival, offline));
+                }
+            }
+        }
+    }
+
+    // IDを名簿の参加者、または当日参加者として指定した時刻で登録する
+    // カード番号は参加者IDに置き換えて登録する
+    pub fn check_in_at(&mut self, id: &str, at: DateTime<FixedOffset>, offline: bool) -> CheckinOutcome {
+        let id = &self.resolve_card(id);
+        let window_state = self
+            .event
+            .checkin_window
+            .as_ref()
+            .map(|window| window.state_at(at))
+            .unwrap_or(WindowState::Open);
+        if let Some(reason) = window_state.rejection_reason() {
+            return CheckinOutcome::Rejected { reason: reason.to_string() };
+        }
+
+        let index = self.event.participants.iter().position(|participant| participant == id);
+        let allow_ontheday = self.settings().arrowtoday;
+        let max_ontheday = self.max_ontheday();
+        let session = self.attendance.entry(self.event.active_session.clone()).or_default();
+
+        let already = match index {
+            Some(index) => session.attendees.contains(&(index as i32)),
+            None => session.ontheday.contains(&id.to_string()),
+        };
+        if already {
+            // 退室済みの参加者は再入室として新しい区間を追加する（到着区分は最初の入室のまま）
+            let Some(arrival) = session.records.get(id).filter(|record| record.is_checked_out()).map(|record| record.arrival) else {
+                return CheckinOutcome::AlreadyRegistered { at: self.registered_at(id) };
+            };
+            self.record_check_ins_at(&[id.to_string()], at, offline);
+            return CheckinOutcome::Registered { arrival };
+        }
+
+        let (attendees, ontheday) = match index {
+            Some(index) => {
+                session.attendees.push(index as i32);
+                session.attendees.sort_unstable();
+                (vec![index as i32], Vec::new())
+            }
+            None if allow_ontheday && session.ontheday.len() >= max_ontheday => {
+                return CheckinOutcome::LimitReached { limit: max_ontheday };
+            }
+            None if allow_ontheday => {
+                session.ontheday.push(id.to_string());
+                (Vec::new(), vec![id.to_string()])
+            }
+            None => return CheckinOutcome::UnknownId,
+        };
+
+        self.record_check_ins_at(&[id.to_string()], at, offline);
+        self.push_change(ChangeKind::Added, attendees, ontheday);
+        CheckinOutcome::Registered { arrival: self.classify_arrival(at) }
+    }
+
+    // 操作IDで重複を除いて登録する
+    // 端末の時刻は未来であれば現在時刻に、受付開始やキューの古さの上限より前であればその時刻に切り上げる
+    pub fn apply_op(&mut self, op: &CheckinOp) -> OpAck {
+        if let Some(result) = self.processed_ops.get(&op.op_id) {
+            return OpAck {
+                op_id: op.op_id.clone(),
+                duplicate: true,
+                result: result.clone(),
+            };
+        }
+
+        let now = Local::now().fixed_offset();
+        let at = op
+            .client_timestamp
+            .as_deref()
+            .and_then(parse_local_datetime)
+            .filter(|at| *at <= now)
+            .map(|at| at.max(self.earliest_op_time(now)))
+            .unwrap_or(now);
+
+        let id = self.resolve_card(&op.id);
+        let outcome = self.check_in_at(&id, at, op.offline);
+        let result = CheckinResult { id, outcome };
+        self.remember_op(&op.op_id, &result, now);
+
+        OpAck {
+            op_id: op.op_id.clone(),
+            duplicate: false,
+            result,
+        }
+    }
+
+    // 端末の時刻として受け付ける最も古い時刻
+    fn earliest_op_time(&self, now: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        let oldest = now - Duration::hours(MAX_OP_AGE_HOURS);
+        self.event
+            .checkin_window
+            .as_ref()
+            .and_then(|window| window.opens_at.as_deref())
+            .and_then(parse_local_datetime)
+            .map_or(oldest, |opens_at| opens_at.max(oldest))
+    }
+
+    // 反映した操作を記録し、上限を超えた分とキューの古さの上限より古い分を削除する
+    fn remember_op(&mut self, op_id: &str, result: &CheckinResult, now: DateTime<FixedOffset>) {
+        self.processed_ops.insert(op_id.to_string(), result.clone());
+        self.processed_op_order.push_back((now, op_id.to_string()));
+
+        let oldest = now - Duration::hours(MAX_OP_AGE_HOURS);
+        while let Some((at, op_id)) = self.processed_op_order.front() {
+            if self.processed_op_order.len() <= MAX_PROCESSED_OPS && *at >= oldest {
+                break;
+            }
+            self.processed_ops.remove(op_id);
+            self.processed_op_order.pop_front();
+        }
+    }
+
+    // 出席を取り消し、取り消せたIDを返す（入退室の記録も削除する）
+    pub fn remove_attendance(&mut self, ids: &[String]) -> Vec<String> {
+        let participants = &self.event.participants;
+        let session = self.attendance.entry(self.event.active_session.clone()).or_default();
+
+        let mut indexes = Vec::new();
+        let mut ontheday = Vec::new();
+        let mut removed = Vec::new();
+        for id in ids {
+            let index = participants
+                .iter()
+                .position(|participant| participant == id)
+                .map(|index| index as i32)
+                .filter(|index| session.attendees.contains(index));
+
+            if let Some(index) = index {
+                session.attendees.retain(|attendee| *attendee != index);
+                indexes.push(index);
+            } else if session.ontheday.contains(id) {
+                session.ontheday.retain(|entry| entry != id);
+                ontheday.push(id.clone());
+            } else {
+                continue;
+            }
+            session.records.remove(id);
+            removed.push(id.clone());
+        }
+
+        self.push_change(ChangeKind::Removed, indexes, ontheday);
+        removed
+    }
+
+    // 出席データが丸ごと入れ替わった場合に呼ぶ（以前の差分は使えないため、端末は全体を取り直す）
+    pub fn reset_changes(&mut self) {
+        self.version += 1;
+        self.changes.clear();
+    }
+
+    // 差分を記録してバージョンを進める（変更がなければ何もしない）
+    fn push_change(&mut self, kind: ChangeKind, attendees: Vec<i32>, ontheday: Vec<String>) {
+        if attendees.is_empty() && ontheday.is_empty() {
+            return;
+        }
+
+        let arrivals = match kind {
+            ChangeKind::Added => {
+                let records = self.attendance.get(&self.event.active_session).map(|session| &session.records);
+                attendees
+                    .iter()
+                    .filter_map(|&index| self.event.participants.get(index as usize))
+                    .chain(ontheday.iter())
+                    .filter_map(|id| records?.get(id).map(|record| (id.clone(), record.arrival)))
+                    .collect()
+            }
+            ChangeKind::Removed => HashMap::new(),
+        };
+
+        self.version += 1;
+        self.changes.push_back(AttendanceDelta {
+            version: self.version,
+            kind,
+            session: self.active_session(),
+            attendees,
+            ontheday,
+            arrivals,
+        });
+        if self.changes.len() > MAX_CHANGES {
+            self.changes.pop_front();
+        }
+    }
+
+    // 指定したバージョンより後の差分（保持している差分で追いつけない場合は None）
+    pub fn changes_since(&self, version: u64) -> Option<Vec<AttendanceDelta>> {
+        if version > self.version {
+            return None;
+        }
+        if version == self.version {
+            return Some(Vec::new());
+        }
+        if self.changes.front().is_none_or(|oldest| oldest.version > version + 1) {
+            return None;
+        }
+
+        Some(self.changes.iter().filter(|delta| delta.version > version).cloned().collect())
+    }
+
+    // 差分で追いつける場合は差分、追いつけない場合は全体を返す
+    pub fn sync_since(&self, version: u64) -> AttendanceSync {
+        match self.changes_since(version) {
+            Some(deltas) => AttendanceSync::Deltas(deltas),
+            None => AttendanceSync::Snapshot(self.snapshot()),
+        }
+    }
+
+    pub fn snapshot(&self) -> AttendanceSnapshot {
+        let session = self.active_session();
+        let attendance = self.current_session();
+        AttendanceSnapshot {
+            version: self.version,
+            arrivals: self.arrival_categories(session.as_deref()),
+            session,
+            attendees: attendance.attendees,
+            ontheday: attendance.ontheday,
+        }
+    }
+
+    // 在室中の参加者を退室させ、退室できたIDを返す
+    pub fn check_out(&mut self, ids: &[String]) -> Vec<String> {
+        let now = Local::now().to_rfc3339();
+        let records = &mut self.current_session_mut().records;
+
+        let mut checked_out = Vec::new();
+        for id in ids {
+            if let Some(interval) = records
+                .get_mut(id)
+                .and_then(|record| record.intervals.last_mut())
+                .filter(|interval| interval.check_out.is_none())
+            {
+                interval.check_out = Some(now.clone());
+                checked_out.push(id.clone());
+            }
+        }
+        checked_out
+    }
+
+    // 現在のセッションで退室済みか（もう一度登録すると再入室になる）
+    pub fn is_checked_out(&self, id: &str) -> bool {
+        self.attendance
+            .get(&self.event.active_session)
+            .and_then(|session| session.records.get(id))
+            .is_some_and(AttendanceRecord::is_checked_out)
+    }
+
+    // 出席登録した時刻（HH:MM）
+    pub fn registered_at(&self, id: &str) -> Option<String> {
+        let session = self.attendance.get(&self.event.active_session)?;
+        let check_in = &session.records.get(id)?.intervals.first()?.check_in;
+        DateTime::parse_from_rfc3339(check_in)
+            .ok()
+            .map(|datetime| datetime.with_timezone(&Local).format("%H:%M").to_string())
+    }
+
+    // 参加者IDごとの到着区分
+    pub fn arrival_categories(&self, session: Option<&str>) -> HashMap<String, ArrivalCategory> {
+        self.attendance
+            .get(&session.map(str::to_string))
+            .map(|session| {
+                session
+                    .records
+                    .iter()
+                    .map(|(id, record)| (id.clone(), record.arrival))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn arrival_statistics(&self, session: Option<&str>) -> ArrivalStatistics {
+        let mut statistics = ArrivalStatistics::default();
+        for arrival in self.arrival_categories(session).values() {
+            match arrival {
+                ArrivalCategory::OnTime => statistics.on_time += 1,
+                ArrivalCategory::Late => statistics.late += 1,
+                ArrivalCategory::Absent => statistics.absent += 1,
+            }
+        }
+        statistics
+    }
+
+    // 参加者ごとの累計出席回数
+    pub fn attendance_totals(&self) -> Vec<AttendanceTotal> {
+        let require_checkout = self.settings().requirecheckout;
+        let session_ids = self.session_ids();
+
+        let mut totals: Vec<AttendanceTotal> = self
+            .event
+            .participants
+            .iter()
+            .map(|id| AttendanceTotal {
+                id: id.clone(),
+                attended: 0,
+                late: 0,
+                sessions: session_ids.len(),
+                ontheday: false,
+            })
+            .collect();
+
+        for session_id in &session_ids {
+            let Some(session) = self.attendance.get(session_id) else {
+                continue;
+            };
+
+            // 退室必須の場合、退室していない出席は数えない。欠席扱いの到着も数えない
+            let arrival = |id: &str| session.records.get(id).map(|record| record.arrival).unwrap_or_default();
+            let counts = |id: &str| {
+                arrival(id) != ArrivalCategory::Absent
+                    && (!require_checkout || session.records.get(id).is_some_and(|record| record.is_checked_out()))
+            };
+            let add = |total: &mut AttendanceTotal| {
+                if counts(&total.id) {
+                    total.attended += 1;
+                    if arrival(&total.id) == ArrivalCategory::Late {
+                        total.late += 1;
+                    }
+                }
+            };
+
+            for &index in &session.attendees {
+                if let Some(total) = usize::try_from(index).ok().and_then(|i| totals.get_mut(i)) {
+                    add(total);
+                }
+            }
+
+            // 当日参加者は名簿にないため、IDごとに集計する
+            for id in &session.ontheday {
+                match totals.iter_mut().find(|total| total.ontheday && &total.id == id) {
+                    Some(total) => add(total),
+                    None => {
+                        let mut total = AttendanceTotal {
+                            id: id.clone(),
+                            attended: 0,
+                            late: 0,
+                            sessions: session_ids.len(),
+                            ontheday: true,
+                        };
+                        add(&mut total);
+                        totals.push(total);
+                    }
+                }
+            }
+        }
+
+        totals
+    }
+
+    // セッションの出席状況をCSVとして出力（在室時間の列を含む）
+    pub fn export_csv(&self, session: Option<&str>) -> String {
+        let attendance = self.session(session);
+        let require_checkout = self.settings().requirecheckout;
+
+        let rows = self
+            .event
+            .participants
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (id.clone(), "名簿", attendance.attendees.contains(&(index as i32))))
+            .chain(attendance.ontheday.iter().map(|id| (id.clone(), "当日", true)));
+
+        let mut csv = String::from("学籍番号,区分,出席,到着区分,入室時刻,退室時刻,在室時間(分),退室済み\n");
+        for (id, kind, attended) in rows {
+            let record = attendance.records.get(&id);
+            let first_in = record
+                .and_then(|record| record.intervals.first())
+                .map(|interval| interval.check_in.clone())
+                .unwrap_or_default();
+            let last_out = record
+                .and_then(|record| record.intervals.last())
+                .and_then(|interval| interval.check_out.clone())
+                .unwrap_or_default();
+            let minutes = record.map(|record| record.total_seconds() / 60).unwrap_or(0);
+            let checked_out = record.is_some_and(|record| record.is_checked_out());
+            let arrival = record.map(|record| record.arrival.label()).unwrap_or_default();
+            let attended = attended
+                && (!require_checkout || checked_out)
+                && record.is_none_or(|record| record.arrival != ArrivalCategory::Absent);
+
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                csv_field(&id),
+                kind,
+                if attended { "出席" } else { "欠席" },
+                arrival,
+                first_in,
+                last_out,
+                minutes,
+                if checked_out { "はい" } else { "いいえ" },
+            ));
+        }
+
+        csv
+    }
+
+    // 端末の接続を記録（同じ端末の切断済みの記録は置き換え、一時停止の状態は引き継ぐ）
+    pub fn connect_device(&mut self, mut device: DeviceInfo) {
+        let (name, ip) = (device.device_name.clone(), device.ip.clone());
+        let same_device = |existing: &DeviceInfo| {
+            !existing.online
+                && if name.is_empty() {
+                    existing.device_name.is_empty() && ip.is_some() && existing.ip == ip
+                } else {
+                    existing.device_name == name
+                }
+        };
+
+        device.locked |= self.devices.values().any(|existing| same_device(existing) && existing.locked);
+        self.devices.retain(|_, existing| !same_device(existing));
+        self.devices.insert(device.socket_id.clone(), device);
+        self.devices_changed = true;
+    }
+
+    // 端末の切断を記録し、切断された端末を返す
+    pub fn disconnect_device(&mut self, socket_id: &str) -> Option<DeviceInfo> {
+        let device = self.devices.get_mut(socket_id).filter(|device| device.online)?;
+        device.online = false;
+        device.last_activity = Local::now().to_rfc3339();
+        self.devices_changed = true;
+        Some(device.clone())
+    }
+
+    // 端末からの操作を記録（checkins は新たに登録された件数）
+    pub fn record_device_activity(&mut self, socket_id: &str, checkins: usize) {
+        if let Some(device) = self.devices.get_mut(socket_id) {
+            device.last_activity = Local::now().to_rfc3339();
+            device.checkins += checkins;
+            self.devices_changed = true;
+        }
+    }
+
+    pub fn set_device_locked(&mut self, socket_id: &str, locked: bool) -> Result<DeviceInfo, String> {
+        let device = self
+            .devices
+            .get_mut(socket_id)
+            .ok_or_else(|| DEVICE_NOT_FOUND.to_string())?;
+        device.locked = locked;
+        self.devices_changed = true;
+        Ok(device.clone())
+    }
+
+    pub fn is_device_locked(&self, socket_id: &str) -> bool {
+        self.devices.get(socket_id).is_some_and(|device| device.locked)
+    }
+
+    // 端末がこのイベントのroomに参加しているか
+    pub fn is_joined(&self, socket_id: &str) -> bool {
+        self.devices.get(socket_id).is_some_and(|device| device.online)
+    }
+
+    // 役割は端末が自己申告するため、主催者が用意したモニタ（サーバーを起動したPC、または端末トークンで参加したモニタ）に限る
+    pub fn is_trusted_monitor(&self, socket_id: &str) -> bool {
+        self.devices
+            .get(socket_id)
+            .is_some_and(|device| device.online && device.role == DeviceRole::Monitor && self.is_trusted_device(device))
+    }
+
+    // 主催者が用意した端末か（サーバーを起動したPC、または有効期限内の端末トークンで参加した端末）
+    // 役割は端末が自己申告するため、役割だけでは判断しない
+    // 参加後にトークンの有効期限が切れた端末は、接続したままでも信頼しない
+    pub fn is_trusted_device(&self, device: &DeviceInfo) -> bool {
+        if device.ip.as_deref().is_some_and(is_loopback) {
+            return true;
+        }
+        let now = Local::now().fixed_offset();
+        device
+            .token_id
+            .as_ref()
+            .and_then(|id| self.device_tokens.get(id))
+            .is_some_and(|token| token.is_valid_at(now))
+    }
+
+    // IPアドレスを禁止し、そのIPアドレスから接続中の端末のソケットIDを返す
+    // サーバーを起動したPC（主催者のモニタ）は禁止できない
+    pub fn ban_ip(&mut self, ip: &str) -> Result<Vec<String>, String> {
+        if is_loopback(ip) {
+            return Err(CANNOT_BAN_LOOPBACK.to_string());
+        }
+        self.banned_ips.insert(ip.to_string());
+        Ok(self
+            .devices
+            .values()
+            .filter(|device| device.online && device.ip.as_deref() == Some(ip))
+            .map(|device| device.socket_id.clone())
+            .collect())
+    }
+
+    pub fn is_ip_banned(&self, ip: &str) -> bool {
+        self.banned_ips.contains(ip)
+    }
+
+    // 端末トークンを発行する（有効期限は発行時刻から valid_for の間）
+    pub fn issue_device_token(&mut self, device_name: &str, role: DeviceRole, valid_for: Duration) -> DeviceToken {
+        let now = Local::now();
+        let id = loop {
+            let id = Uuid::new_v4().simple().to_string()[..8].to_string();
+            if !self.device_tokens.contains_key(&id) {
+                break id;
+            }
+        };
+        let token = DeviceToken {
+            id: id.clone(),
+            token: Uuid::new_v4().simple().to_string(),
+            device_name: device_name.to_string(),
+            role,
+            created_at: now.to_rfc3339(),
+            expires_at: (now + valid_for).to_rfc3339(),
+        };
+        self.device_tokens.insert(id, token.clone());
+        token
+    }
+
+    // 端末トークンを無効にし、そのトークンで接続中の端末のソケットIDを返す
+    pub fn revoke_device_token(&mut self, id: &str) -> Result<(DeviceToken, Vec<String>), String> {
+        let token = self
+            .device_tokens
+            .remove(id)
+            .ok_or_else(|| DEVICE_TOKEN_NOT_FOUND.to_string())?;
+        let sockets = self
+            .devices
+            .values()
+            .filter(|device| device.online && device.token_id.as_deref() == Some(id))
+            .map(|device| device.socket_id.clone())
+            .collect();
+        Ok((token, sockets))
+    }
+
+    // 参加時に送信された端末トークンを確認する（同じトークンで同時に参加できるのは1台まで）
+    pub fn validate_device_token(&self, token: &str, now: DateTime<FixedOffset>) -> Result<DeviceToken, String> {
+        let found = self
+            .device_tokens
+            .values()
+            .find(|candidate| token_matches(&candidate.token, token))
+            .ok_or_else(|| "端末トークンが無効です".to_string())?;
+        if !found.is_valid_at(now) {
+            return Err("端末トークンの有効期限が切れています".to_string());
+        }
+        let in_use = self
+            .devices
+            .values()
+            .any(|device| device.online && device.token_id.as_deref() == Some(found.id.as_str()));
+        if in_use {
+            return Err("この端末トークンは他の端末で使用中です".to_string());
+        }
+        Ok(found.clone())
+    }
+
+    // セルフ出席登録（同じIPアドレスから別のIDを登録しようとした場合は代理登録とみなして拒否する）
+    pub fn self_check_in(&mut self, ip: Option<&str>, id: &str, at: DateTime<FixedOffset>) -> Result<CheckinOutcome, String> {
+        if let Some(previous) = ip.and_then(|ip| self.self_checkins.get(ip)) {
+            if previous != id {
+                return Err("この端末からは既に別のIDで出席登録されています".to_string());
+            }
+        }
+        let outcome = self.check_in_at(id, at, false);
+        if let (CheckinOutcome::Registered { .. }, Some(ip)) = (&outcome, ip) {
+            self.self_checkins.insert(ip.to_string(), id.to_string());
+        }
+        Ok(outcome)
+    }
+
+    // QRコードのトークンの使用を記録する
+    // 同じ端末が同じトークンを再度使った場合と、既に別のIDを登録した端末からの登録は拒否する
+    pub fn use_qr_token(&mut self, nonce: &str, issued_at: u64, client: &str, id: &str, now: u64) -> Result<(), String> {
+        // 有効期限が切れたトークンは署名の検証で拒否されるため、記録から除く
+        self.used_qr_tokens
+            .retain(|_, (issued, _)| issued.saturating_add(QR_TOKEN_TTL_SECS) >= now);
+        if self.self_checkins.get(client).is_some_and(|previous| previous != id) {
+            return Err("この端末からは既に別のIDで出席登録されています".to_string());
+        }
+        let (_, clients) = self
+            .used_qr_tokens
+            .entry(nonce.to_string())
+            .or_insert_with(|| (issued_at, HashSet::new()));
+        if !clients.insert(client.to_string()) {
+            return Err("このQRコードは使用済みです。もう一度読み取ってください".to_string());
+        }
+        self.self_checkins.insert(client.to_string(), id.to_string());
+        Ok(())
+    }
+
+    // 名簿にないIDはカード番号とみなし、対応表にあれば参加者IDに置き換える
+    pub fn resolve_card(&self, id: &str) -> String {
+        if self.event.participants.iter().any(|participant| participant == id) {
+            return id.to_string();
+        }
+        self.card_ids.get(id.trim()).cloned().unwrap_or_else(|| id.to_string())
+    }
+
+    // カード番号の対応表をCSV（1列目がカード番号、2列目が参加者ID）から読み込む
+    // 名簿がある場合、名簿にない参加者IDの行（見出し行を含む）は読み込まない
+    pub fn import_card_ids(&mut self, csv: &str, replace: bool) -> CardImport {
+        if replace {
+            self.card_ids.clear();
+        }
+        let mut result = CardImport::default();
+        // Excelで保存したCSVの先頭にはBOMが付く
+        for line in csv.trim_start_matches('\u{feff}').lines() {
+            let mut columns = line.split(',').map(|column| column.trim().trim_matches('"'));
+            let (card, id) = (columns.next().unwrap_or_default(), columns.next().unwrap_or_default());
+            if card.is_empty() || card.starts_with('#') {
+                continue;
+            }
+            let known =
+                self.event.participants.is_empty() || self.event.participants.iter().any(|participant| participant == id);
+            if id.is_empty() || !known {
+                result.skipped += 1;
+                continue;
+            }
+            self.card_ids.insert(card.to_string(), id.to_string());
+            result.imported += 1;
+        }
+        result
+    }
+
+    // 発行順の端末トークン一覧
+    pub fn device_tokens(&self) -> Vec<DeviceToken> {
+        let mut tokens: Vec<DeviceToken> = self.device_tokens.values().cloned().collect();
+        tokens.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        tokens
+    }
+
+    // 接続順の端末一覧
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        let mut devices: Vec<DeviceInfo> = self.devices.values().cloned().collect();
+        devices.sort_by(|a, b| a.connected_since.cmp(&b.connected_since));
+        devices
+    }
+
+    // 前回の通知以降に変更があれば端末一覧を返す
+    pub fn take_devices_changed(&mut self) -> Option<Vec<DeviceInfo>> {
+        if !self.devices_changed {
+            return None;
+        }
+        self.devices_changed = false;
+        Some(self.devices())
+    }
+
+    // 名簿の末尾に参加者を追加する（出席データは名簿の位置で記録しているため、既存の順番は変えない）
+    pub fn add_participants(&mut self, ids: &[String]) -> usize {
+        let mut known: HashSet<String> = self.event.participants.iter().cloned().collect();
+        let mut added = 0;
+        for id in ids {
+            let id = id.trim();
+            if id.is_empty() || !known.insert(id.to_string()) {
+                continue;
+            }
+            self.event.participants.push(id.to_string());
+            added += 1;
+        }
+        added
+    }
+
+    pub fn to_saved(&self) -> SavedEvent {
+        SavedEvent {
+            event: self.event.clone(),
+            saved_settings: self.saved_settings.clone(),
+            attendance: self.attendance.iter().map(|(session, attendance)| (session.clone(), attendance.clone())).collect(),
+            logs: self.logs.clone(),
+            banned_ips: self.banned_ips.clone(),
+            device_tokens: self.device_tokens(),
+            self_checkin_secret: Some(self.self_checkin_secret.clone()),
+            qr_secret: Some(self.qr_secret.clone()),
+            card_ids: self.card_ids.clone(),
+        }
+    }
+
+    pub fn from_saved(saved: SavedEvent) -> Self {
+        let mut state = Self::new(saved.event);
+        state.saved_settings = saved.saved_settings;
+        state.attendance = saved.attendance.into_iter().collect();
+        state.logs = saved.logs;
+        state.banned_ips = saved.banned_ips;
+        state.device_tokens = saved.device_tokens.into_iter().map(|token| (token.id.clone(), token)).collect();
+        if let Some(secret) = saved.self_checkin_secret {
+            state.self_checkin_secret = secret;
+        }
+        if let Some(secret) = saved.qr_secret {
+            state.qr_secret = secret;
+        }
+        state.card_ids = saved.card_ids;
+        state
+    }
+
+    // ログを保存し、送信用に返す
+    pub fn push_log(&mut self, level: &str, message: String) -> LogEntry {
+        let entry = LogEntry::new(level, message);
+        self.logs.push(entry.clone());
+        if self.logs.len() > MAX_LOGS {
+            let overflow = self.logs.len() - MAX_LOGS;
+            self.logs.drain(..overflow);
+        }
+        entry
+    }
+}
+
+// CSVの1フィールドをエスケープ
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// イベントIDごとの状態を保持する唯一のストア（Tauriコマンドとソケットで共有）
+// 読み込み→変更→保存はイベントごとのロックを保持したまま行うため、同時に届いた登録が失われない
+#[derive(Debug, Default)]
+pub struct EventStore {
+    events: RwLock<HashMap<String, Arc<Mutex<EventState>>>>,
+    // 参加コード → イベントID
+    join_codes: RwLock<HashMap<String, String>>,
+}
+
+impl EventStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 同じIDのイベントを置き換えた場合は、以前のイベントの参加コードでは参加できなくする
+    // （ロックは issue_join_code と同じく参加コード→イベントの順に取る）
+    pub fn insert(&self, id: String, state: EventState) {
+        let mut join_codes = self.join_codes.write().unwrap();
+        let join_code = state.event.join_code.clone();
+        let previous = self.events.write().unwrap().insert(id.clone(), Arc::new(Mutex::new(state)));
+        if let Some(previous) = previous.and_then(|previous| previous.lock().unwrap().event.join_code.clone()) {
+            if join_codes.get(&previous) == Some(&id) {
+                join_codes.remove(&previous);
+            }
+        }
+        if let Some(code) = join_code {
+            join_codes.insert(code, id);
+        }
+    }
+
+    // 使われていないIDを割り当てて登録する（IDの確認と登録の間に他の登録が入らないようにロックを保持する）
+    pub fn insert_new(&self, mut new_id: impl FnMut() -> String, mut state: EventState) -> String {
+        let mut events = self.events.write().unwrap();
+        let id = loop {
+            let id = new_id();
+            if !events.contains_key(&id) {
+                break id;
+            }
+        };
+        state.event.roomid = Some(id.clone());
+        events.insert(id.clone(), Arc::new(Mutex::new(state)));
+        id
+    }
+
+    // 参加コードを発行する（発行済みの場合は新しいコードに置き換え、以前のコードでは参加できなくする）
+    pub fn issue_join_code(&self, id: &str, mut new_code: impl FnMut() -> String) -> Option<String> {
+        let mut join_codes = self.join_codes.write().unwrap();
+        let code = loop {
+            let code = new_code();
+            if !join_codes.contains_key(&code) {
+                break code;
+            }
+        };
+        let previous = self.update(id, |state| state.event.join_code.replace(code.clone()))?;
+        if let Some(previous) = previous {
+            join_codes.remove(&previous);
+        }
+        join_codes.insert(code.clone(), id.to_string());
+        Some(code)
+    }
+
+    // イベントIDまたは参加コードからイベントIDを求める（コードの大文字・小文字、空白とハイフンは区別しない）
+    pub fn resolve(&self, id_or_code: &str) -> Option<String> {
+        if self.contains(id_or_code) {
+            return Some(id_or_code.to_string());
+        }
+        let code: String = id_or_code
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        self.join_codes.read().unwrap().get(&code).cloned()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        let events = self.events.read().unwrap();
+        events.contains_key(id)
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        let events = self.events.read().unwrap();
+        events.keys().cloned().collect()
+    }
+
+    fn entry(&self, id: &str) -> Option<Arc<Mutex<EventState>>> {
+        let events = self.events.read().unwrap();
+        events.get(id).cloned()
+    }
+
+    pub fn get(&self, id: &str) -> Option<EventState> {
+        self.read(id, |state| state.clone())
+    }
+
+    pub fn read<R>(&self, id: &str, f: impl FnOnce(&EventState) -> R) -> Option<R> {
+        let entry = self.entry(id)?;
+        let state = entry.lock().unwrap();
+        Some(f(&state))
+    }
+
+    // イベントのロックを保持したまま状態を更新する
+    pub fn update<R>(&self, id: &str, f: impl FnOnce(&mut EventState) -> R) -> Option<R> {
+        let entry = self.entry(id)?;
+        let mut state = entry.lock().unwrap();
+        Some(f(&mut state))
+    }
+
+    // すべてのイベントをJSONファイルに保存する（書き込み途中で失敗しても元のファイルを壊さない）
+    pub fn save_to_file(&self, path: &Path) -> Result<usize, String> {
+        let mut ids = self.ids();
+        ids.sort();
+        let saved: Vec<SavedEvent> = ids.iter().filter_map(|id| self.read(id, |state| state.to_saved())).collect();
+        let json = serde_json::to_string_pretty(&saved).map_err(|e| format!("保存データを作成できませんでした: {}", e))?;
+
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, json).map_err(|e| format!("ファイルに保存できませんでした: {}", e))?;
+        std::fs::rename(&temp, path).map_err(|e| format!("ファイルに保存できませんでした: {}", e))?;
+        Ok(saved.len())
+    }
+
+    // JSONファイルからイベントを読み込む（同じIDのイベントは置き換える）
+    pub fn load_from_file(&self, path: &Path) -> Result<usize, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("ファイルを読み込めませんでした: {}", e))?;
+        let saved: Vec<SavedEvent> =
+            serde_json::from_str(&json).map_err(|e| format!("保存データの形式が正しくありません: {}", e))?;
+
+        let mut loaded = 0;
+        for event in saved {
+            let Some(id) = event.event.roomid.clone() else {
+                continue;
+            };
+            self.insert(id, EventState::from_saved(event));
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    // 失敗しうる更新（イベントが見つからない場合もエラーにする）
+    // 状態全体を複製して戻すと参加者やログが多いイベントで重いため、f は確認をすべて済ませてから変更すること
+    pub fn try_update<R>(&self, id: &str, f: impl FnOnce(&mut EventState) -> Result<R, String>) -> Result<R, String> {
+        let entry = self.entry(id).ok_or_else(|| EVENT_NOT_FOUND.to_string())?;
+        let mut state = entry.lock().unwrap();
+        f(&mut state)
+    }
+}
+
+static EVENT_STORE: OnceLock<Arc<EventStore>> = OnceLock::new();
+
+pub fn get_event_store() -> Arc<EventStore> {
+    EVENT_STORE.get_or_init(|| Arc::new(EventStore::new())).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    fn event_state(participants: &[&str]) -> EventState {
+        let event: Eventstruct = serde_json::from_value(serde_json::json!({
+            "eventname": "test",
+            "eventinfo": "",
+            "participants": participants,
+            "arrowtoday": true,
+            "autotodayregister": false,
+            "nolist": false,
+            "soukai": false,
+        }))
+        .unwrap();
+        EventState::new(event)
+    }
+
+    fn intervals(state: &EventState, id: &str) -> usize {
+        state.current_session().records[id].intervals.len()
+    }
+
+    #[test]
+    fn checked_out_participant_can_reenter() {
+        let mut state = event_state(&["a", "b"]);
+        state.register_attendees(&[0]);
+        state.register_ontheday(&["x".to_string()]);
+        state.check_out(&["a".to_string(), "x".to_string()]);
+
+        assert_eq!(state.register_attendees(&[0]).0, vec!["a".to_string()]);
+        assert_eq!(state.register_ontheday(&["x".to_string()]), vec!["x".to_string()]);
+        assert!(state.current_session().records["a"].is_present());
+        assert_eq!(intervals(&state, "a"), 2);
+        assert_eq!(intervals(&state, "x"), 2);
+
+        // 在室中の再登録は新しい区間を追加しない
+        assert!(state.register_attendees(&[0]).0.is_empty());
+        assert_eq!(intervals(&state, "a"), 2);
+    }
+
+    #[test]
+    fn check_in_at_reports_reentry_as_registered() {
+        let mut state = event_state(&["a"]);
+        let now = Local::now().fixed_offset();
+        assert!(matches!(state.check_in_at("a", now, false), CheckinOutcome::Registered { .. }));
+        assert!(matches!(state.check_in_at("a", now, false), CheckinOutcome::AlreadyRegistered { .. }));
+
+        state.check_out(&["a".to_string()]);
+        assert!(matches!(state.check_in_at("a", now, false), CheckinOutcome::Registered { .. }));
+        assert_eq!(intervals(&state, "a"), 2);
+        assert_eq!(state.current_session().attendees, vec![0]);
+    }
+
+    #[test]
+    fn card_numbers_are_resolved_in_every_check_in_path() {
+        let mut state = event_state(&["a", "b"]);
+        state.import_card_ids("1001,a\n1002,b\n", true);
+        let now = Local::now().fixed_offset();
+
+        assert!(matches!(state.check_in_at("1001", now, false), CheckinOutcome::Registered { .. }));
+        assert_eq!(state.register_ontheday(&["1002".to_string(), "x".to_string()]), vec!["b".to_string(), "x".to_string()]);
+        let session = state.current_session();
+        assert_eq!(session.attendees, vec![0, 1]);
+        assert_eq!(session.ontheday, vec!["x".to_string()]);
+        assert!(!session.records.contains_key("1001"));
+    }
+
+    fn op(op_id: &str, id: &str, at: DateTime<FixedOffset>) -> CheckinOp {
+        CheckinOp {
+            op_id: op_id.to_string(),
+            id: id.to_string(),
+            client_timestamp: Some(at.to_rfc3339()),
+            offline: true,
+        }
+    }
+
+    fn checked_in_at(state: &EventState, id: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(&state.current_session().records[id].intervals[0].check_in).unwrap()
+    }
+
+    #[test]
+    fn apply_op_clamps_backdated_timestamps() {
+        let mut state = event_state(&["a", "b"]);
+        let now = Local::now().fixed_offset();
+        state.apply_op(&op("1", "a", now - Duration::days(2)));
+        assert!(checked_in_at(&state, "a") >= now - Duration::hours(MAX_OP_AGE_HOURS));
+
+        let opens_at = (now - Duration::hours(1)).with_nanosecond(0).unwrap();
+        state.event.checkin_window = Some(crate::CheckinWindow {
+            opens_at: Some(opens_at.to_rfc3339()),
+            ..Default::default()
+        });
+        state.apply_op(&op("2", "b", now - Duration::hours(2)));
+        assert_eq!(checked_in_at(&state, "b"), opens_at);
+    }
+
+    #[test]
+    fn processed_ops_are_capped() {
+        let mut state = event_state(&["a"]);
+        let now = Local::now().fixed_offset();
+        for i in 0..=MAX_PROCESSED_OPS {
+            state.apply_op(&op(&i.to_string(), "a", now));
+        }
+        assert_eq!(state.processed_ops.len(), MAX_PROCESSED_OPS);
+        assert!(!state.processed_ops.contains_key("0"));
+        assert!(state.apply_op(&op(&MAX_PROCESSED_OPS.to_string(), "a", now)).duplicate);
+    }
+
+    fn with_changes(count: usize) -> EventState {
+        let mut state = event_state(&[]);
+        for i in 0..count {
+            state.register_ontheday(&[format!("x{}", i)]);
+        }
+        state
+    }
+
+    #[test]
+    fn changes_since_returns_deltas_within_queue() {
+        let state = with_changes(3);
+        let deltas = state.changes_since(1).unwrap();
+        assert_eq!(deltas.iter().map(|delta| delta.version).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(matches!(state.sync_since(1), AttendanceSync::Deltas(_)));
+    }
+
+    #[test]
+    fn changes_since_current_version_is_empty() {
+        let state = with_changes(3);
+        assert_eq!(state.changes_since(state.version).map(|deltas| deltas.len()), Some(0));
+    }
+
+    #[test]
+    fn changes_since_future_version_falls_back_to_snapshot() {
+        let state = with_changes(3);
+        assert!(state.changes_since(state.version + 1).is_none());
+        assert!(matches!(state.sync_since(state.version + 1), AttendanceSync::Snapshot(_)));
+    }
+
+    #[test]
+    fn changes_since_older_than_queue_falls_back_to_snapshot() {
+        let state = with_changes(MAX_CHANGES + 10);
+        assert_eq!(state.changes.len(), MAX_CHANGES);
+        assert!(state.changes_since(0).is_none());
+        assert!(state.changes_since(9).is_none());
+        assert_eq!(state.changes_since(10).map(|deltas| deltas.len()), Some(MAX_CHANGES));
+        match state.sync_since(0) {
+            AttendanceSync::Snapshot(snapshot) => {
+                assert_eq!(snapshot.version, state.version);
+                assert_eq!(snapshot.ontheday.len(), MAX_CHANGES + 10);
+            }
+            AttendanceSync::Deltas(_) => panic!("expected a snapshot"),
+        }
+    }
+
+    fn session(id: &str) -> Session {
+        Session {
+            id: id.to_string(),
+            date: "2026-04-01".to_string(),
+            label: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn first_session_takes_over_existing_attendance() {
+        let mut state = event_state(&["a", "b"]);
+        state.register_attendees(&[0]);
+        let version = state.version;
+
+        assert!(state.add_session(session("s1")));
+        assert_eq!(state.active_session().as_deref(), Some("s1"));
+        assert_eq!(state.current_session().attendees, vec![0]);
+        assert!(!state.attendance.contains_key(&None));
+        assert!(state.version > version);
+        assert!(state.changes_since(version).is_none());
+
+        // 2つ目以降のセッションを追加してもアクティブなセッションは変わらない
+        assert!(!state.add_session(session("s2")));
+        assert_eq!(state.active_session().as_deref(), Some("s1"));
+    }
+
+    #[test]
+    fn switching_sessions_keeps_attendance_per_session() {
+        let mut state = event_state(&["a", "b"]);
+        state.add_session(session("s1"));
+        state.add_session(session("s2"));
+        state.register_attendees(&[0]);
+
+        let version = state.version;
+        assert_eq!(state.set_active_session("s2").unwrap().id, "s2");
+        assert!(state.current_session().attendees.is_empty());
+        assert!(state.changes_since(version).is_none());
+        state.register_attendees(&[0, 1]);
+        assert_eq!(state.session(Some("s1")).attendees, vec![0]);
+        assert_eq!(state.current_session().attendees, vec![0, 1]);
+
+        assert!(state.set_active_session("unknown").is_err());
+        assert_eq!(state.active_session().as_deref(), Some("s2"));
+    }
+
+    #[test]
+    fn attendance_totals_count_every_session() {
+        let mut state = event_state(&["a", "b"]);
+        state.add_session(session("s1"));
+        state.add_session(session("s2"));
+        state.register_attendees(&[0]);
+        state.register_ontheday(&["x".to_string()]);
+        state.set_active_session("s2").unwrap();
+        state.register_attendees(&[0, 1]);
+
+        let totals: Vec<(String, usize, usize, bool)> = state
+            .attendance_totals()
+            .into_iter()
+            .map(|total| (total.id, total.attended, total.sessions, total.ontheday))
+            .collect();
+        assert_eq!(
+            totals,
+            vec![
+                ("a".to_string(), 2, 2, false),
+                ("b".to_string(), 1, 2, false),
+                ("x".to_string(), 1, 2, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn export_csv_is_scoped_to_the_session() {
+        let mut state = event_state(&["a", "b"]);
+        state.add_session(session("s1"));
+        state.add_session(session("s2"));
+        state.register_attendees(&[0]);
+        state.set_active_session("s2").unwrap();
+        state.register_attendees(&[1]);
+
+        let first = state.export_csv(Some("s1"));
+        assert!(first.contains("a,名簿,出席,"));
+        assert!(first.contains("b,名簿,欠席,"));
+        let second = state.export_csv(Some("s2"));
+        assert!(second.contains("a,名簿,欠席,"));
+        assert!(second.contains("b,名簿,出席,"));
+    }
+
+    #[test]
+    fn loopback_addresses_cannot_be_banned() {
+        let mut state = event_state(&[]);
+        assert!(state.ban_ip("127.0.0.1").is_err());
+        assert!(state.ban_ip("::1").is_err());
+        assert!(!state.is_ip_banned("127.0.0.1"));
+
+        assert!(state.ban_ip("192.168.0.20").is_ok());
+        assert!(state.is_ip_banned("192.168.0.20"));
+    }
+
+    #[test]
+    fn device_token_is_checked_until_expiry() {
+        let mut state = event_state(&[]);
+        let now = Local::now().fixed_offset();
+        let token = state.issue_device_token("kiosk", DeviceRole::Kiosk, Duration::hours(1));
+
+        assert_eq!(state.validate_device_token(&token.token, now).unwrap().id, token.id);
+        assert!(state.validate_device_token(&token.token[1..], now).is_err());
+        assert!(state.validate_device_token(&token.token, now + Duration::hours(2)).is_err());
+    }
+
+    #[test]
+    fn failed_update_leaves_state_unchanged() {
+        let store = EventStore::new();
+        let mut state = event_state(&[]);
+        state.add_session(session("s1"));
+        state.add_session(session("s2"));
+        store.insert("room".to_string(), state);
+
+        assert!(store.try_update("room", |state| state.set_active_session("missing")).is_err());
+        assert!(store.try_update("room", |state| state.set_device_locked("missing", true)).is_err());
+        assert!(store.try_update("missing", |state| state.set_active_session("s2")).is_err());
+        assert_eq!(store.read("room", |state| state.event.active_session.clone()).flatten().as_deref(), Some("s1"));
+    }
+
+    #[test]
+    fn replacing_event_drops_previous_join_code() {
+        let store = EventStore::new();
+        store.insert("room".to_string(), event_state(&[]));
+        let old_code = store.issue_join_code("room", || "OLDCODE".to_string()).unwrap();
+        assert_eq!(store.resolve(&old_code).as_deref(), Some("room"));
+
+        let mut replaced = event_state(&[]);
+        replaced.event.join_code = Some("NEWCODE".to_string());
+        store.insert("room".to_string(), replaced);
+
+        assert_eq!(store.resolve(&old_code), None);
+        assert_eq!(store.resolve("NEWCODE").as_deref(), Some("room"));
+    }
+
+    #[test]
+    fn saved_events_round_trip_through_file() {
+        let path = std::env::temp_dir().join(format!("list-checker-store-{}.json", std::process::id()));
+        let store = EventStore::new();
+        let mut state = event_state(&["a", "b"]);
+        state.event.roomid = Some("room".to_string());
+        state.register_attendees(&[1]);
+        state.register_ontheday(&["x".to_string()]);
+        store.insert("room".to_string(), state);
+
+        assert_eq!(store.save_to_file(&path).unwrap(), 1);
+        let loaded = EventStore::new();
+        let result = loaded.load_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap(), 1);
+        let state = loaded.get("room").unwrap();
+        assert_eq!(state.event.participants, vec!["a".to_string(), "b".to_string()]);
+        assert!(!state.current_session().records.contains_key("a"));
+        assert!(state.current_session().records["b"].is_present());
+        assert!(state.current_session().records["x"].is_present());
+    }
+
+    #[test]
+    fn broken_state_file_is_rejected() {
+        let path = std::env::temp_dir().join(format!("list-checker-broken-{}.json", std::process::id()));
+        std::fs::write(&path, "{").unwrap();
+        let store = EventStore::new();
+        let result = store.load_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+        assert!(store.ids().is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+// 複数の端末から同時に出席登録しても、登録が失われないことを確認する
+use list_checker_tauri_lib::{create_event, get_event_store, serve_socketio, Eventstruct};
+use rust_socketio::asynchronous::{Client, ClientBuilder};
+use rust_socketio::{Event, Payload, TransportType};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+const CLIENTS: usize = 50;
+const SCANS_PER_CLIENT: usize = 10;
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// イベントを送信して応答の ok を返す（応答がない場合は None）
+async fn emit_and_wait(client: &Client, event: &str, data: Value) -> Option<bool> {
+    let (acked, mut on_acked) = tokio::sync::mpsc::unbounded_channel();
+    client
+        .emit_with_ack(event, data, ACK_TIMEOUT, move |payload: Payload, _| -> BoxFuture {
+            let acked = acked.clone();
+            Box::pin(async move {
+                // 応答は引数の配列として届く
+                #[allow(deprecated)]
+                let ok = match payload {
+                    Payload::Text(values) => values.first().and_then(|args| args[0]["ok"].as_bool()),
+                    _ => None,
+                };
+                let _ = acked.send(ok.unwrap_or(false));
+            })
+        })
+        .await
+        .ok()?;
+    tokio::time::timeout(ACK_TIMEOUT, on_acked.recv()).await.ok().flatten()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_register_attendees_loses_nothing() {
+    let participants: Vec<String> = (0..CLIENTS * SCANS_PER_CLIENT)
+        .map(|i| format!("s{:05}", i))
+        .collect();
+    let event: Eventstruct = serde_json::from_value(json!({
+        "eventname": "stress",
+        "eventinfo": "",
+        "participants": participants,
+        "arrowtoday": false,
+        "autotodayregister": false,
+        "nolist": false,
+        "soukai": false,
+    }))
+    .unwrap();
+    let uuid = create_event(event);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(serve_socketio(vec![listener]));
+
+    // 各端末は自分の担当分だけを1件ずつ、他の端末と同時に送信する
+    let mut handles = Vec::new();
+    for client_index in 0..CLIENTS {
+        let uuid = uuid.clone();
+        handles.push(tokio::spawn(async move {
+            // 接続の確立前に送信したイベントは破棄され、負荷が高いと再接続することもあるため、
+            // 実際の端末と同じく接続するたびにjoinし直す
+            let (joined, mut on_joined) = tokio::sync::mpsc::unbounded_channel();
+            let join_uuid = uuid.clone();
+            let client = ClientBuilder::new(format!("http://{}", address))
+                .transport_type(TransportType::Websocket)
+                .on(Event::Connect, move |_, client: Client| -> BoxFuture {
+                    let joined = joined.clone();
+                    let uuid = join_uuid.clone();
+                    // コールバック内で応答を待つと受信処理が止まるため、別のタスクで待つ
+                    tokio::spawn(async move {
+                        if emit_and_wait(&client, "join", json!(uuid)).await == Some(true) {
+                            let _ = joined.send(());
+                        }
+                    });
+                    Box::pin(async {})
+                })
+                .connect()
+                .await
+                .expect("failed to connect");
+            tokio::time::timeout(Duration::from_secs(30), on_joined.recv())
+                .await
+                .expect("join was not acknowledged");
+
+            // 応答がなければ送り直す（登録済みのIDを再送しても重複して登録されない）
+            for scan in 0..SCANS_PER_CLIENT {
+                let index = (client_index * SCANS_PER_CLIENT + scan) as i32;
+                let data = json!({ "uuid": uuid, "attendeeindex": [index] });
+                while emit_and_wait(&client, "register_attendees", data.clone()).await != Some(true) {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+
+            client
+        }));
+    }
+
+    let mut clients = Vec::new();
+    for handle in handles {
+        clients.push(handle.await.unwrap());
+    }
+
+    for client in clients {
+        client.disconnect().await.unwrap();
+    }
+
+    // 応答を受け取った時点で登録は反映されている
+    let expected = CLIENTS * SCANS_PER_CLIENT;
+    let attendees = get_event_store()
+        .read(&uuid, |state| state.current_session().attendees)
+        .unwrap();
+    let expected_indexes: Vec<i32> = (0..expected as i32).collect();
+    assert_eq!(attendees, expected_indexes);
+
+    let records = get_event_store()
+        .read(&uuid, |state| state.current_session().records.len())
+        .unwrap();
+    assert_eq!(records, expected);
+}
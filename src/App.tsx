@@ -280,7 +280,8 @@ function App() {
               uuid: result1 as string,
             },
           });
-          invoke("debug_run_server");
+          // ポートの確保まで待ってから起動状態を確認する
+          await invoke("start_server");
           const serverState = await invoke("server_check");
           setServerRunning(serverState as boolean);
 
@@ -306,7 +306,9 @@ const EventRegistration = () => {
       console.log("送信データ:", sendData);
       const result = await invoke("register_event", { data: sendData });
       setTimeout(() => {
-        invoke("debug_run_server");
+        invoke("start_server").catch((error) =>
+          console.error("サーバーの起動に失敗しました:", error)
+        );
         setUuid(result as string);
         setDataSended(true);
         setCurrentStep(steps.length - 1);
@@ -324,7 +326,9 @@ const EventRegistration = () => {
       console.log("送信データ:", sendData);
       const result = await invoke("register_event", { data: sendData });
       setTimeout(() => {
-        invoke("debug_run_server");
+        invoke("start_server").catch((error) =>
+          console.error("サーバーの起動に失敗しました:", error)
+        );
         setUuid(result as string);
         setDataSended(true);
         setCurrentStep(steps.length - 1);
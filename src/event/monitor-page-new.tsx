@@ -12,8 +12,10 @@ import {
   Download,
   Info,
   X,
+  Tablet,
 } from "lucide-react";
 import { io } from "socket.io-client";
+import { QRCodeSVG } from "qrcode.react";
 import Papa from "papaparse";
 import * as XLSX from "xlsx";
 
@@ -35,11 +37,51 @@ type LogEntry = {
   message: string;
 };
 
+type AttendanceDelta = {
+  version: number;
+  attendees: number[];
+  ontheday: string[];
+};
+
+type AttendanceSnapshot = AttendanceDelta;
+
+type Device = {
+  socket_id: string;
+  role: "kiosk" | "monitor" | "unknown";
+  device_name: string;
+  ip: string | null;
+  connected_since: string;
+  last_activity: string;
+  checkins: number;
+  online: boolean;
+  locked: boolean;
+};
+
+type DeviceToken = {
+  id: string;
+  token: string;
+  device_name: string;
+  role: "kiosk" | "monitor" | "unknown";
+  created_at: string;
+  expires_at: string;
+};
+
 function MonitorPageNew() {
   const [expectedAttendees, setExpectedAttendees] = useState<Attendee[]>([]);
   const [dataFetched, setDataFetched] = useState(false);
   const [roomName, setRoomName] = useState<string>("");
   const [roomInfo, setRoomInfo] = useState<string>("");
+  const [joinCode, setJoinCode] = useState<string | null>(null);
+  const [deviceTokens, setDeviceTokens] = useState<DeviceToken[]>([]);
+  const [tokenDeviceName, setTokenDeviceName] = useState("");
+  const [requireDeviceToken, setRequireDeviceToken] = useState(false);
+  const [selfCheckin, setSelfCheckin] = useState(false);
+  const [selfCheckinCode, setSelfCheckinCode] = useState<{
+    code: string;
+    expires_in: number;
+  } | null>(null);
+  const [qrCheckin, setQrCheckin] = useState(false);
+  const [qrToken, setQrToken] = useState<string | null>(null);
   const [onTheDay, setOnTheDay] = useState<string[]>([]);
   const [settings, setSettings] = useState<Settings>({
     arrowtoday: false,
@@ -51,7 +93,14 @@ function MonitorPageNew() {
   const [showDownloadModal, setShowDownloadModal] = useState(false);
   const [showInfoModal, setShowInfoModal] = useState(false);
   const [loading, setLoading] = useState(true);
-  const [activeTab, setActiveTab] = useState<"list" | "logs">("list");
+  const [activeTab, setActiveTab] = useState<"list" | "logs" | "devices">(
+    "list"
+  );
+  const [devices, setDevices] = useState<Device[]>([]);
+  const [announcementText, setAnnouncementText] = useState("");
+  const [announcementSeverity, setAnnouncementSeverity] = useState<
+    "info" | "warning" | "critical"
+  >("info");
   const [activityLogs, setActivityLogs] = useState<LogEntry[]>([]);
   const [logFilter, setLogFilter] = useState<string[]>([
     "server",
@@ -64,6 +113,8 @@ function MonitorPageNew() {
   const domain = decodeURIComponent(domainRaw);
   const socketRef = useRef<any>(null);
   const expectedAttendeesCopyRef = useRef<string[]>([]);
+  // 出席データのバージョン（全体の同期を受け取るまでは null）
+  const attendanceVersionRef = useRef<number | null>(null);
 
   const { uuid } = useParams<{ uuid: string }>();
 
@@ -94,6 +145,10 @@ function MonitorPageNew() {
           autotodayregister: boolean;
           soukai: boolean;
           nolist: boolean;
+          join_code?: string;
+          require_device_token?: boolean;
+          self_checkin?: boolean;
+          qr_checkin?: boolean;
         } | null>("get_event", { uuid });
 
         if (!response) {
@@ -114,6 +169,11 @@ function MonitorPageNew() {
           expectedAttendeesCopyRef.current = response.participants;
           setRoomName(response.eventname);
           setRoomInfo(response.eventinfo);
+          setJoinCode(response.join_code ?? null);
+          setRequireDeviceToken(response.require_device_token ?? false);
+          setSelfCheckin(response.self_checkin ?? false);
+          setQrCheckin(response.qr_checkin ?? false);
+          loadDeviceTokens();
           setSettings({
             autotodayregister: response.autotodayregister,
             arrowtoday: response.arrowtoday,
@@ -121,9 +181,10 @@ function MonitorPageNew() {
             noList: response.nolist,
           });
 
-          // Socket.IOに接続
-          socketRef.current = io("http://" + domain);
-          console.log("Connecting to socket server at:http://" + domain);
+          // Socket.IOに接続（サーバーを起動したPCとして扱われるよう、ループバックで接続する）
+          const serverUrl = "http://127.0.0.1:" + (domain.split(":").pop() || "50345");
+          socketRef.current = io(serverUrl);
+          console.log("Connecting to socket server at:" + serverUrl);
 
           // 接続時にroomに参加
           socketRef.current.on("connect", () => {
@@ -131,7 +192,30 @@ function MonitorPageNew() {
               "Connected to socket server, joining room with UUID:",
               uuid
             );
-            socketRef.current.emit("join", uuid);
+            // 同期はjoinが完了してから要求する（参加していないイベントの同期は拒否される）
+            socketRef.current.emit(
+              "join",
+              {
+                uuid,
+                role: "monitor",
+                device_name: "モニタ（アプリ）",
+              },
+              (response: any) => {
+                if (!response?.ok) {
+                  console.error("Failed to join room:", response?.message);
+                  return;
+                }
+                // 再接続時は切断中の差分のみ受け取る
+                if (attendanceVersionRef.current !== null) {
+                  socketRef.current?.emit("sync_since", {
+                    uuid,
+                    version: attendanceVersionRef.current,
+                  });
+                } else {
+                  socketRef.current?.emit("sync_all_data", uuid);
+                }
+              }
+            );
           });
 
           socketRef.current.on("register_attendees_return", (data: any) => {
@@ -148,6 +232,32 @@ function MonitorPageNew() {
             }
           });
 
+          // 出席データの差分を反映
+          socketRef.current.on("attendance_added", (delta: AttendanceDelta) => {
+            applyAttendanceDelta(delta, true);
+          });
+
+          socketRef.current.on(
+            "attendance_removed",
+            (delta: AttendanceDelta) => {
+              applyAttendanceDelta(delta, false);
+            }
+          );
+
+          socketRef.current.on("attendance_version", (version: number) => {
+            attendanceVersionRef.current = version;
+          });
+
+          socketRef.current.on(
+            "attendance_snapshot",
+            (snapshot: AttendanceSnapshot) => {
+              console.log("Attendance snapshot received:", snapshot);
+              attendanceVersionRef.current = snapshot.version;
+              dataDeCompression(snapshot.attendees);
+              setOnTheDay(snapshot.ontheday);
+            }
+          );
+
           // 設定変更イベントのリスナーを追加
           socketRef.current.on("settings_change_return", (data: any) => {
             console.log("Settings changed from server:", data);
@@ -179,14 +289,27 @@ function MonitorPageNew() {
             }
           });
 
+          // 接続端末の一覧を受信
+          socketRef.current.on("devices_update", (data: Device[]) => {
+            setDevices(data);
+          });
+          invoke<Device[] | null>("get_devices", { uuid }).then((data) => {
+            if (data) {
+              setDevices(data);
+            }
+          });
+
+          // 参加コードが再発行された
+          socketRef.current.on("join_code_update", (code: string) => {
+            setJoinCode(code);
+          });
+
           // アクティビティログを受信
           socketRef.current.on("activity_log", (log: LogEntry) => {
             console.log("Activity log received:", log);
             setActivityLogs((prev) => [...prev, log]);
           });
 
-          socketRef.current.emit("sync_all_data", uuid);
-
           return () => {
             if (socketRef.current) {
               socketRef.current.disconnect();
@@ -221,6 +344,31 @@ function MonitorPageNew() {
     setExpectedAttendees(updatedAttendees);
   };
 
+  // 取りこぼしがあれば差分を要求し直す
+  const applyAttendanceDelta = (delta: AttendanceDelta, added: boolean) => {
+    const version = attendanceVersionRef.current;
+    if (version === null || delta.version <= version) {
+      return;
+    }
+    if (delta.version !== version + 1) {
+      console.log("Missed attendance changes, requesting since", version);
+      socketRef.current?.emit("sync_since", { uuid, version });
+      return;
+    }
+    attendanceVersionRef.current = delta.version;
+
+    setExpectedAttendees((prev) =>
+      prev.map((attendee, index) =>
+        delta.attendees.includes(index) ? { ...attendee, attended: added } : attendee
+      )
+    );
+    setOnTheDay((prev) =>
+      added
+        ? prev.concat(delta.ontheday.filter((id) => !prev.includes(id)))
+        : prev.filter((id) => !delta.ontheday.includes(id))
+    );
+  };
+
   const attendedCount = expectedAttendees.filter((a) => a.attended).length;
   const totalCount = expectedAttendees.length;
   const attendanceRate =
@@ -369,6 +517,214 @@ function MonitorPageNew() {
     );
   };
 
+  const formatTime = (value: string) =>
+    new Date(value).toLocaleTimeString("ja-JP", {
+      hour: "2-digit",
+      minute: "2-digit",
+      second: "2-digit",
+    });
+
+  // 端末の切断・一時停止・IP禁止（結果は devices_update と動作ログで反映される）
+  const runDeviceAction = async (
+    command: string,
+    args: Record<string, unknown>,
+    confirmMessage?: string
+  ) => {
+    if (confirmMessage && !confirm(confirmMessage)) {
+      return;
+    }
+    try {
+      await invoke(command, { uuid, ...args });
+    } catch (error) {
+      alert(`操作に失敗しました: ${error}`);
+    }
+  };
+
+  // 全端末にお知らせを送信（表示時間は30秒）
+  const sendAnnouncement = async () => {
+    if (!announcementText.trim()) {
+      return;
+    }
+    try {
+      await invoke("send_announcement", {
+        uuid,
+        message: announcementText,
+        severity: announcementSeverity,
+        durationSecs: 30,
+      });
+      setAnnouncementText("");
+    } catch (error) {
+      alert(`お知らせの送信に失敗しました: ${error}`);
+    }
+  };
+
+  // セルフ出席登録のコード（30秒ごとにサーバーで切り替わる）を1秒ごとに取得する
+  useEffect(() => {
+    if (!selfCheckin) {
+      setSelfCheckinCode(null);
+      return;
+    }
+    const update = () =>
+      invoke<{ code: string; expires_in: number } | null>(
+        "get_self_checkin_code",
+        { uuid }
+      ).then(setSelfCheckinCode);
+    update();
+    const timer = setInterval(update, 1000);
+    return () => clearInterval(timer);
+  }, [selfCheckin, uuid]);
+
+  const toggleSelfCheckin = async (enabled: boolean) => {
+    try {
+      await invoke("set_self_checkin", { uuid, enabled });
+      setSelfCheckin(enabled);
+    } catch (error) {
+      alert(`設定を変更できませんでした: ${error}`);
+    }
+  };
+
+  // QRコードのトークン（有効期限が数秒のため、サーバーが指定した間隔で取り直す）
+  useEffect(() => {
+    if (!qrCheckin) {
+      setQrToken(null);
+      return;
+    }
+    let cancelled = false;
+    let timer: ReturnType<typeof setTimeout>;
+    const update = async () => {
+      const result = await invoke<{ token: string; refresh_in: number } | null>(
+        "get_qr_token",
+        { uuid }
+      );
+      if (cancelled) return;
+      setQrToken(result?.token ?? null);
+      timer = setTimeout(update, (result?.refresh_in ?? 5) * 1000);
+    };
+    update();
+    return () => {
+      cancelled = true;
+      clearTimeout(timer);
+    };
+  }, [qrCheckin, uuid]);
+
+  const toggleQrCheckin = async (enabled: boolean) => {
+    try {
+      await invoke("set_qr_checkin", { uuid, enabled });
+      setQrCheckin(enabled);
+    } catch (error) {
+      alert(`設定を変更できませんでした: ${error}`);
+    }
+  };
+
+  // 端末トークン（キオスクごとに発行し、1台ずつ無効にできる）
+  const loadDeviceTokens = async () => {
+    const tokens = await invoke<DeviceToken[] | null>("get_device_tokens", {
+      uuid,
+    });
+    setDeviceTokens(tokens ?? []);
+  };
+
+  const createDeviceToken = async () => {
+    if (!tokenDeviceName.trim()) {
+      return;
+    }
+    try {
+      await invoke("create_device_token", {
+        uuid,
+        deviceName: tokenDeviceName,
+        role: "kiosk",
+      });
+      setTokenDeviceName("");
+      await loadDeviceTokens();
+    } catch (error) {
+      alert(`端末トークンを発行できませんでした: ${error}`);
+    }
+  };
+
+  const deleteDeviceToken = async (token: DeviceToken) => {
+    if (
+      !confirm(
+        `${token.device_name} のトークンを無効にしますか？接続中の場合は切断されます。`
+      )
+    ) {
+      return;
+    }
+    try {
+      await invoke("delete_device_token", { uuid, tokenId: token.id });
+      await loadDeviceTokens();
+    } catch (error) {
+      alert(`端末トークンを無効にできませんでした: ${error}`);
+    }
+  };
+
+  const toggleRequireDeviceToken = async (required: boolean) => {
+    try {
+      await invoke("require_device_token", { uuid, required });
+      setRequireDeviceToken(required);
+    } catch (error) {
+      alert(`設定を変更できませんでした: ${error}`);
+    }
+  };
+
+  // 学生証のバーコード・ICカードの番号と参加者IDの対応表（CSV）を読み込む
+  const importCardIds = async (file: File) => {
+    try {
+      const result = await invoke<{ imported: number; skipped: number }>(
+        "upload_card_ids",
+        { uuid, csv: await file.text(), replace: true }
+      );
+      alert(
+        `${result.imported}件を読み込みました` +
+          (result.skipped > 0
+            ? `（${result.skipped}件は名簿にないため読み込みませんでした）`
+            : "")
+      );
+    } catch (error) {
+      alert(`対応表を読み込めませんでした: ${error}`);
+    }
+  };
+
+  const deviceTokenUrl = (token: DeviceToken) =>
+    `http://${localIP}:50080/attendance.html?uuid=${uuid}&server=${domain}&token=${token.token}`;
+
+  // 参加コードを発行し直す（以前のコードでは参加できなくなる）
+  const regenerateJoinCode = async () => {
+    const message = joinCode
+      ? "参加コードを再発行しますか？以前のコードでは参加できなくなります。"
+      : "参加コードを発行しますか？";
+    if (!confirm(message)) {
+      return;
+    }
+    try {
+      setJoinCode(await invoke<string>("regenerate_join_code", { uuid }));
+    } catch (error) {
+      alert(`参加コードを発行できませんでした: ${error}`);
+    }
+  };
+
+  // 不具合報告用にサーバーのログファイルを書き出す
+  const exportServerLogs = async () => {
+    try {
+      const path = await invoke<string | null>("export_logs");
+      if (path) {
+        alert(`ログを書き出しました: ${path}`);
+      }
+    } catch (error) {
+      alert(`ログの書き出しに失敗しました: ${error}`);
+    }
+  };
+
+  const openLogFolder = async () => {
+    try {
+      await invoke("open_log_folder");
+    } catch (error) {
+      alert(`ログフォルダを開けませんでした: ${error}`);
+    }
+  };
+
+  const roleLabel = (role: Device["role"]) =>
+    role === "kiosk" ? "受付端末" : role === "monitor" ? "モニタ" : "不明";
+
   const filteredLogs = activityLogs.filter((log) =>
     logFilter.includes(log.level)
   );
@@ -706,6 +1062,57 @@ function MonitorPageNew() {
                   </div>
                 </motion.div>
               )}
+
+              {/* セルフ出席登録のコード */}
+              <div className="bg-white rounded-2xl shadow-xl border border-gray-100 p-6">
+                <label className="flex items-center gap-2 text-sm text-gray-600">
+                  <input
+                    type="checkbox"
+                    checked={selfCheckin}
+                    onChange={(e) => toggleSelfCheckin(e.target.checked)}
+                  />
+                  スマートフォンからのセルフ出席登録を受け付ける
+                </label>
+                {selfCheckin && selfCheckinCode && (
+                  <div className="mt-4 text-center">
+                    <div className="text-6xl font-bold font-mono tracking-widest text-gray-800">
+                      {selfCheckinCode.code}
+                    </div>
+                    <div className="mt-2 text-sm text-gray-500">
+                      あと{selfCheckinCode.expires_in}秒で切り替わります
+                    </div>
+                    <div className="mt-3 text-xs font-mono text-gray-500 break-all">
+                      http://{localIP}:50080/self-checkin.html?uuid={uuid}
+                      &server={domain}
+                    </div>
+                  </div>
+                )}
+              </div>
+
+              {/* QRコードによる出席登録 */}
+              <div className="bg-white rounded-2xl shadow-xl border border-gray-100 p-6">
+                <label className="flex items-center gap-2 text-sm text-gray-600">
+                  <input
+                    type="checkbox"
+                    checked={qrCheckin}
+                    onChange={(e) => toggleQrCheckin(e.target.checked)}
+                  />
+                  QRコードを読み取った端末からのみ出席登録を受け付ける
+                </label>
+                {qrCheckin && qrToken && (
+                  <div className="mt-4 flex flex-col items-center">
+                    <QRCodeSVG
+                      value={`http://${localIP}:50080/self-checkin.html?uuid=${uuid}&server=${domain}&qr=${encodeURIComponent(
+                        qrToken
+                      )}`}
+                      size={256}
+                    />
+                    <div className="mt-2 text-sm text-gray-500">
+                      QRコードを読み取って出席登録してください
+                    </div>
+                  </div>
+                )}
+              </div>
             </motion.div>
 
             {/* 出席者リスト */}
@@ -783,6 +1190,38 @@ function MonitorPageNew() {
                         />
                       )}
                     </motion.button>
+                    <motion.button
+                      whileHover={{ scale: 1.02 }}
+                      whileTap={{ scale: 0.98 }}
+                      onClick={() => setActiveTab("devices")}
+                      className={`relative px-6 py-3 font-semibold transition-all rounded-t-xl ${
+                        activeTab === "devices"
+                          ? "text-indigo-700"
+                          : "text-gray-500 hover:text-gray-700 hover:bg-gray-50"
+                      }`}
+                    >
+                      <div className="flex items-center gap-2">
+                        <Tablet className="w-4 h-4" />
+                        接続端末
+                        {devices.some((device) => !device.online) && (
+                          <span className="px-2 py-0.5 text-xs font-bold bg-red-500 text-white rounded-full shadow-lg">
+                            {devices.filter((device) => !device.online).length}
+                          </span>
+                        )}
+                      </div>
+                      {activeTab === "devices" && (
+                        <motion.div
+                          layoutId="activeTab"
+                          className="absolute bottom-0 left-0 right-0 h-0.5 bg-gradient-to-r from-indigo-600 to-purple-600"
+                          initial={false}
+                          transition={{
+                            type: "spring",
+                            stiffness: 500,
+                            damping: 30,
+                          }}
+                        />
+                      )}
+                    </motion.button>
                   </div>
                 </div>
 
@@ -888,6 +1327,211 @@ function MonitorPageNew() {
                           </div>
                         )}
                       </motion.div>
+                    ) : activeTab === "devices" ? (
+                      <motion.div
+                        key="devices"
+                        initial={{ opacity: 0, x: 20 }}
+                        animate={{ opacity: 1, x: 0 }}
+                        exit={{ opacity: 0, x: -20 }}
+                        transition={{ duration: 0.3 }}
+                      >
+                        {/* お知らせ送信 */}
+                        <div className="p-4 bg-gray-50 border-b border-gray-100 flex gap-2">
+                          <input
+                            value={announcementText}
+                            onChange={(e) => setAnnouncementText(e.target.value)}
+                            placeholder="全端末へのお知らせ"
+                            className="flex-1 px-3 py-2 text-sm rounded-lg border border-gray-200"
+                          />
+                          <select
+                            value={announcementSeverity}
+                            onChange={(e) =>
+                              setAnnouncementSeverity(
+                                e.target.value as "info" | "warning" | "critical"
+                              )
+                            }
+                            className="px-2 py-2 text-sm rounded-lg border border-gray-200"
+                          >
+                            <option value="info">通常</option>
+                            <option value="warning">注意</option>
+                            <option value="critical">緊急</option>
+                          </select>
+                          <button
+                            onClick={sendAnnouncement}
+                            className="px-4 py-2 text-sm font-medium rounded-lg bg-indigo-600 text-white hover:bg-indigo-700"
+                          >
+                            送信
+                          </button>
+                        </div>
+
+                        {/* 端末トークン */}
+                        <div className="p-4 bg-gray-50 border-b border-gray-100 space-y-3">
+                          <div className="flex gap-2">
+                            <input
+                              value={tokenDeviceName}
+                              onChange={(e) => setTokenDeviceName(e.target.value)}
+                              placeholder="端末名（例: 受付1）"
+                              className="flex-1 px-3 py-2 text-sm rounded-lg border border-gray-200"
+                            />
+                            <button
+                              onClick={createDeviceToken}
+                              className="px-4 py-2 text-sm font-medium rounded-lg bg-indigo-600 text-white hover:bg-indigo-700"
+                            >
+                              トークン発行
+                            </button>
+                          </div>
+                          <label className="flex items-center gap-2 text-sm text-gray-600">
+                            <input
+                              type="checkbox"
+                              checked={requireDeviceToken}
+                              onChange={(e) =>
+                                toggleRequireDeviceToken(e.target.checked)
+                              }
+                            />
+                            トークンを持たない端末の参加を禁止する
+                          </label>
+                          <label className="flex items-center gap-2 text-sm text-gray-600">
+                            カード番号の対応表（CSV）
+                            <input
+                              type="file"
+                              accept=".csv,text/csv"
+                              onChange={(e) => {
+                                const file = e.target.files?.[0];
+                                if (file) {
+                                  importCardIds(file);
+                                }
+                                e.target.value = "";
+                              }}
+                              className="text-xs"
+                            />
+                          </label>
+                          {deviceTokens.map((token) => (
+                            <div
+                              key={token.id}
+                              className="flex items-center gap-2 text-sm"
+                            >
+                              <div className="flex-1 min-w-0">
+                                <div className="font-medium text-gray-800">
+                                  {token.device_name}
+                                  <span className="ml-2 text-xs text-gray-500">
+                                    期限 {formatTime(token.expires_at)}
+                                  </span>
+                                </div>
+                                <div className="text-xs font-mono text-gray-500 break-all">
+                                  {deviceTokenUrl(token)}
+                                </div>
+                              </div>
+                              <button
+                                onClick={() =>
+                                  navigator.clipboard.writeText(
+                                    deviceTokenUrl(token)
+                                  )
+                                }
+                                className="px-3 py-1 text-xs font-medium rounded-full border border-gray-200 bg-white text-gray-700 hover:bg-gray-100"
+                              >
+                                URLをコピー
+                              </button>
+                              <button
+                                onClick={() => deleteDeviceToken(token)}
+                                className="px-3 py-1 text-xs font-medium rounded-full border border-red-200 bg-red-50 text-red-700 hover:bg-red-100"
+                              >
+                                無効化
+                              </button>
+                            </div>
+                          ))}
+                        </div>
+
+                        <div className="divide-y divide-gray-100">
+                          {devices.length === 0 ? (
+                            <div className="p-12 text-center text-gray-400">
+                              <p>接続している端末はありません</p>
+                            </div>
+                          ) : (
+                            devices.map((device) => (
+                              <div
+                                key={device.socket_id}
+                                className={`p-4 ${
+                                  device.online ? "" : "bg-red-50/50"
+                                }`}
+                              >
+                                <div className="flex items-center justify-between">
+                                  <div>
+                                    <div className="text-lg font-medium text-gray-800">
+                                      {device.device_name ||
+                                        `ID: ${device.socket_id.slice(0, 8)}`}
+                                    </div>
+                                    <div className="text-sm text-gray-500">
+                                      {roleLabel(device.role)}
+                                      {device.ip && ` ・ ${device.ip}`}
+                                      {` ・ 接続 ${formatTime(
+                                        device.connected_since
+                                      )}`}
+                                      {` ・ 最終操作 ${formatTime(
+                                        device.last_activity
+                                      )}`}
+                                      {` ・ 登録 ${device.checkins}件`}
+                                    </div>
+                                  </div>
+                                  {device.online && device.role !== "monitor" && (
+                                    <div className="flex gap-2 ml-auto mr-3">
+                                      <button
+                                        onClick={() =>
+                                          runDeviceAction("admin_lock_device", {
+                                            socketId: device.socket_id,
+                                            locked: !device.locked,
+                                          })
+                                        }
+                                        className="px-3 py-1 text-xs font-medium rounded-full border border-amber-200 bg-amber-50 text-amber-700 hover:bg-amber-100"
+                                      >
+                                        {device.locked ? "再開" : "一時停止"}
+                                      </button>
+                                      <button
+                                        onClick={() =>
+                                          runDeviceAction(
+                                            "admin_kick_device",
+                                            { socketId: device.socket_id },
+                                            "この端末を切断しますか？"
+                                          )
+                                        }
+                                        className="px-3 py-1 text-xs font-medium rounded-full border border-red-200 bg-red-50 text-red-700 hover:bg-red-100"
+                                      >
+                                        切断
+                                      </button>
+                                      {device.ip && (
+                                        <button
+                                          onClick={() =>
+                                            runDeviceAction(
+                                              "admin_ban_ip",
+                                              { ip: device.ip },
+                                              `${device.ip} からの接続をイベント終了まで禁止しますか？`
+                                            )
+                                          }
+                                          className="px-3 py-1 text-xs font-medium rounded-full border border-red-300 bg-red-100 text-red-800 hover:bg-red-200"
+                                        >
+                                          IP禁止
+                                        </button>
+                                      )}
+                                    </div>
+                                  )}
+                                  {device.online && device.locked ? (
+                                    <span className="inline-flex items-center px-4 py-2 rounded-full text-sm font-medium bg-amber-100 text-amber-700">
+                                      一時停止中
+                                    </span>
+                                  ) : device.online ? (
+                                    <span className="inline-flex items-center px-4 py-2 rounded-full text-sm font-medium bg-green-100 text-green-700">
+                                      接続中
+                                    </span>
+                                  ) : (
+                                    <span className="inline-flex items-center px-4 py-2 rounded-full text-sm font-medium bg-red-100 text-red-700">
+                                      切断
+                                    </span>
+                                  )}
+                                </div>
+                              </div>
+                            ))
+                          )}
+                        </div>
+                      </motion.div>
                     ) : (
                       <motion.div
                         key="logs"
@@ -917,6 +1561,20 @@ function MonitorPageNew() {
                                 </button>
                               )
                             )}
+                            <div className="flex gap-2 ml-auto">
+                              <button
+                                onClick={openLogFolder}
+                                className="px-3 py-1 text-xs font-medium rounded-full border border-gray-200 bg-white text-gray-600 hover:bg-gray-100"
+                              >
+                                ログフォルダを開く
+                              </button>
+                              <button
+                                onClick={exportServerLogs}
+                                className="px-3 py-1 text-xs font-medium rounded-full border border-gray-200 bg-white text-gray-600 hover:bg-gray-100"
+                              >
+                                サーバーログを書き出す
+                              </button>
+                            </div>
                           </div>
                         </div>
 
@@ -1089,6 +1747,21 @@ function MonitorPageNew() {
                   </div>
                 </div>
 
+                <div className="p-4 bg-gray-50 rounded-xl">
+                  <div className="flex items-center justify-between mb-1">
+                    <div className="text-sm text-gray-500">参加コード</div>
+                    <button
+                      onClick={regenerateJoinCode}
+                      className="text-xs text-indigo-600 hover:text-indigo-800"
+                    >
+                      {joinCode ? "再発行" : "発行"}
+                    </button>
+                  </div>
+                  <div className="text-xl font-mono tracking-widest bg-white p-3 rounded-lg border border-gray-200">
+                    {joinCode ?? "未発行"}
+                  </div>
+                </div>
+
                 <div className="p-4 bg-gray-50 rounded-xl">
                   <div className="text-sm text-gray-500 mb-3">出席登録URL</div>
                   <div className="text-xs font-mono bg-white p-3 rounded-lg border border-gray-200 break-all">
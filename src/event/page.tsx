@@ -388,8 +388,9 @@ function EventPage() {
         existingAttendee.attended = true;
         setExpectedAttendees([...expectedAttendees]);
         console.log("Selected attendee:", attendeeId);
+        // 今回登録した参加者のみ送信する（サーバーは既存の出席者に追加する）
         socketRef.current.emit("register_attendees", {
-          attendeeindex: dataCompression(),
+          attendeeindex: [expectedAttendees.indexOf(existingAttendee)],
           uuid: uuid,
         });
       }
@@ -405,7 +406,7 @@ function EventPage() {
             setOnTheDay((prev) => [...prev, attendeeId]);
             onTheDayCopyRef.current = [...onTheDayCopyRef.current, attendeeId];
             socketRef.current.emit("register_ontheday", {
-              ontheday: onTheDayCopyRef.current,
+              ontheday: [attendeeId],
               uuid: uuid,
             });
           } else {
@@ -420,7 +421,7 @@ function EventPage() {
                 attendeeId,
               ];
               socketRef.current.emit("register_ontheday", {
-                ontheday: onTheDayCopyRef.current,
+                ontheday: [attendeeId],
                 uuid: uuid,
               });
             }
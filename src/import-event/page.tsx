@@ -184,7 +184,9 @@ const ImportEvent = () => {
 
       // サーバー起動（awaitしない - バックグラウンドで起動）
       console.log("サーバーを起動しています...");
-      invoke("debug_run_server"); // awaitを削除
+      invoke("start_server").catch((error) =>
+        console.error("サーバーの起動に失敗しました:", error)
+      );
 
       // サーバーの起動を待機
       console.log("サーバーの起動を待機中...");
@@ -6,7 +6,9 @@ import { BrowserRouter, Routes, Route } from "react-router";
 
 const CreateRoomMain = React.lazy(() => import("./create-room/main/page"));
 const JoinRoom = React.lazy(() => import("./join-room/page"));
-const DebugPage = React.lazy(() => import("./debug/page"));
+// デバッグページが呼ぶ debug_hashmap は debug フィーチャーでのみ組み込まれるため、ページも同時に有効にした場合のみ含める
+const DEBUG_ENABLED = import.meta.env.VITE_LIST_CHECKER_DEBUG === "true";
+const DebugPage = DEBUG_ENABLED ? React.lazy(() => import("./debug/page")) : null;
 const MonitorPage = React.lazy(() => import("./event/monitor-page-new"));
 const EventListPage = React.lazy(() => import("./event-list/page"));
 const ImportEvent = React.lazy(() => import("./import-event/page"));
@@ -40,7 +42,7 @@ ReactDOM.createRoot(document.getElementById("root") as HTMLElement).render(
 
             <Route path="import-event" element={<ImportEvent />} />
 
-            <Route path="debug" element={<DebugPage />} />
+            {DebugPage && <Route path="debug" element={<DebugPage />} />}
           </Routes>
         </React.Suspense>
       </BrowserRouter>